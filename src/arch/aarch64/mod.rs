@@ -3,12 +3,44 @@ use sel4_common::{
     structures_gen::cap_tag, utils::pageBitsForSize, vm_rights::vm_rights_from_word,
 };
 
+use super::{report_unknown_arch_cap, KernelLevel, KernelWindow};
 use crate::{
     capability::{cap_arch_func, zombie::zombie_func},
     cte::{cte_t, deriveCap_ret},
 };
 use sel4_common::structures_gen::{cap, cap_null_cap};
 
+/// 内核窗口的配置，`hypervisor`下内核运行在EL2，`aarch64_va52`选择52位虚拟地址。
+/// `mdb_node`中的指针都通过`encode_mdb_ptr`和`decode_mdb_ptr`读写，不依赖`sel4_common`中48位的位域布局
+pub const KERNEL_WINDOW: KernelWindow = KernelWindow {
+    va_bits: if cfg!(feature = "aarch64_va52") {
+        52
+    } else {
+        48
+    },
+    level: if cfg!(feature = "hypervisor") {
+        KernelLevel::EL2
+    } else {
+        KernelLevel::EL1
+    },
+};
+
+/// 按`KERNEL_WINDOW`编码写入`mdb_node`的指针
+#[inline]
+pub const fn encode_mdb_ptr(addr: usize) -> usize {
+    KERNEL_WINDOW.encode(addr)
+}
+
+/// 按`KERNEL_WINDOW`描述的虚拟地址宽度和特权级恢复指针
+#[inline]
+pub const fn decode_mdb_ptr(raw: usize) -> usize {
+    KERNEL_WINDOW.decode(raw)
+}
+
+/// `seL4_VCPUBits`
+#[cfg(feature = "hypervisor")]
+pub const VCPU_SIZE_BITS: usize = 12;
+
 impl cap_arch_func for cap {
     fn arch_updatedata(&self, _preserve: bool, _new_data: u64) -> Self {
         #[cfg(feature = "enable_smc")]
@@ -29,20 +61,18 @@ impl cap_arch_func for cap {
         return self.clone();
     }
     fn arch_is_cap_revocable(&self, _src_cap: &cap) -> bool {
-        #[cfg(feature = "enable_smc")]
-        {
-            match self.get_tag() {
-                cap_tag::cap_smc_cap => {
-                    return cap::cap_smc_cap(self).get_capSMCBadge()
-                        != cap::cap_smc_cap(_src_cap).get_capSMCBadge()
-                }
-                _ => {
-                    return false;
-                }
+        match self.get_tag() {
+            #[cfg(feature = "enable_smc")]
+            cap_tag::cap_smc_cap => {
+                cap::cap_smc_cap(self).get_capSMCBadge()
+                    != cap::cap_smc_cap(_src_cap).get_capSMCBadge()
             }
+            #[cfg(feature = "arm_smmu")]
+            cap_tag::cap_sid_cap => _src_cap.get_tag() == cap_tag::cap_sid_control_cap,
+            #[cfg(feature = "arm_smmu")]
+            cap_tag::cap_cb_cap => _src_cap.get_tag() == cap_tag::cap_cb_control_cap,
+            _ => false,
         }
-        #[cfg(not(feature = "enable_smc"))]
-        return false;
     }
     fn get_cap_ptr(&self) -> usize {
         match self.get_tag() {
@@ -64,6 +94,8 @@ impl cap_arch_func for cap {
             // cap_tag::CapPageGlobalDirectoryCap => self.get_pgd_base_ptr(),
             cap_tag::cap_asid_control_cap => 0,
             cap_tag::cap_asid_pool_cap => cap::cap_asid_pool_cap(self).get_capASIDPool() as usize,
+            #[cfg(feature = "hypervisor")]
+            cap_tag::cap_vcpu_cap => cap::cap_vcpu_cap(self).get_capVCPUPtr() as usize,
             #[cfg(feature = "kernel_mcs")]
             cap_tag::cap_reply_cap => cap::cap_reply_cap(self).get_capReplyPtr() as usize,
             #[cfg(feature = "kernel_mcs")]
@@ -154,8 +186,20 @@ impl cte_t {
             cap_tag::cap_smc_cap => {
                 ret.capability = capability.clone();
             }
+            #[cfg(feature = "hypervisor")]
+            cap_tag::cap_vcpu_cap => {
+                ret.capability = capability.clone();
+            }
+            #[cfg(feature = "arm_smmu")]
+            cap_tag::cap_sid_control_cap
+            | cap_tag::cap_sid_cap
+            | cap_tag::cap_cb_control_cap
+            | cap_tag::cap_cb_cap => {
+                ret.capability = capability.clone();
+            }
             _ => {
-                panic!(" Invalid arch cap type : {}", capability.get_tag() as usize);
+                report_unknown_arch_cap("arch_derive_cap", capability.get_tag());
+                ret.status = exception_t::EXCEPTION_SYSCALL_ERROR;
             }
         }
         ret
@@ -220,12 +264,52 @@ pub fn arch_same_region_as(cap1: &cap, cap2: &cap) -> bool {
                 return true;
             }
         }
-        _ => panic!("unknown cap"),
+        #[cfg(feature = "hypervisor")]
+        cap_tag::cap_vcpu_cap => {
+            if cap2.get_tag() == cap_tag::cap_vcpu_cap {
+                return cap::cap_vcpu_cap(cap1).get_capVCPUPtr()
+                    == cap::cap_vcpu_cap(cap2).get_capVCPUPtr();
+            }
+        }
+        #[cfg(feature = "arm_smmu")]
+        cap_tag::cap_sid_control_cap => {
+            return matches!(
+                cap2.get_tag(),
+                cap_tag::cap_sid_control_cap | cap_tag::cap_sid_cap
+            );
+        }
+        #[cfg(feature = "arm_smmu")]
+        cap_tag::cap_sid_cap => {
+            if cap2.get_tag() == cap_tag::cap_sid_cap {
+                return cap::cap_sid_cap(cap1).get_capSID() == cap::cap_sid_cap(cap2).get_capSID();
+            }
+        }
+        #[cfg(feature = "arm_smmu")]
+        cap_tag::cap_cb_control_cap => {
+            return matches!(
+                cap2.get_tag(),
+                cap_tag::cap_cb_control_cap | cap_tag::cap_cb_cap
+            );
+        }
+        #[cfg(feature = "arm_smmu")]
+        cap_tag::cap_cb_cap => {
+            if cap2.get_tag() == cap_tag::cap_cb_cap {
+                return cap::cap_cb_cap(cap1).get_capCB() == cap::cap_cb_cap(cap2).get_capCB();
+            }
+        }
+        _ => report_unknown_arch_cap("arch_same_region_as", cap1.get_tag()),
     }
     false
 }
 
 pub fn arch_same_object_as(cap1: &cap, cap2: &cap) -> bool {
+    // 与`irq_control`和`irq_handler`一样，控制`cap`和由它派生出的`cap`不是同一个对象
+    #[cfg(feature = "arm_smmu")]
+    if (cap1.get_tag() == cap_tag::cap_sid_control_cap && cap2.get_tag() == cap_tag::cap_sid_cap)
+        || (cap1.get_tag() == cap_tag::cap_cb_control_cap && cap2.get_tag() == cap_tag::cap_cb_cap)
+    {
+        return false;
+    }
     if cap1.get_tag() == cap_tag::cap_frame_cap && cap2.get_tag() == cap_tag::cap_frame_cap {
         return cap::cap_frame_cap(cap1).get_capFBasePtr()
             == cap::cap_frame_cap(cap2).get_capFBasePtr()
@@ -1,11 +1,126 @@
+//! 各架构相关的`cap`操作
+//!
+//! 每个架构模块都需要提供`cap_arch_func`的实现、`cte_t::arch_derive_cap`、`arch_mask_cap_rights`、
+//! `arch_same_region_as`和`arch_same_object_as`。
+//!
+//! 遇到未知的arch cap时不会panic，而是通过`report_unknown_arch_cap`报告后按失败处理：
+//! 派生返回`EXCEPTION_SYSCALL_ERROR`，比较返回`false`。
+//!
+//! 此外还需要提供`encode_mdb_ptr`和`decode_mdb_ptr`，决定`mdb_node`中的指针如何保存和恢复。
+use core::sync::atomic::{AtomicPtr, Ordering};
+
 #[cfg(target_arch = "riscv64")]
 mod riscv64;
 
 #[cfg(target_arch = "riscv64")]
 pub use riscv64::*;
 
+#[cfg(target_arch = "riscv32")]
+mod riscv32;
+
+#[cfg(target_arch = "riscv32")]
+pub use riscv32::*;
+
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
 pub use aarch64::*;
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::*;
+
+#[cfg(target_arch = "loongarch64")]
+mod loongarch64;
+
+#[cfg(target_arch = "loongarch64")]
+pub use loongarch64::*;
+
+/// 遇到未知arch cap时的回调，参数为出错的函数名和`cap_tag`
+pub type UnknownArchCapHook = fn(&'static str, u64);
+
+static UNKNOWN_ARCH_CAP_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// 设置遇到未知arch cap时的回调，传入`None`取消
+pub fn set_unknown_arch_cap_hook(hook: Option<UnknownArchCapHook>) {
+    let ptr = hook.map_or(core::ptr::null_mut(), |f| f as *mut ());
+    UNKNOWN_ARCH_CAP_HOOK.store(ptr, Ordering::Release);
+}
+
+pub(crate) fn report_unknown_arch_cap(site: &'static str, tag: u64) {
+    log::warn!("{}: unknown arch cap type {}", site, tag);
+    let ptr = UNKNOWN_ARCH_CAP_HOOK.load(Ordering::Acquire);
+    if !ptr.is_null() {
+        let hook = unsafe { core::mem::transmute::<*mut (), UnknownArchCapHook>(ptr) };
+        hook(site, tag);
+    }
+}
+
+/// 将只保留了低`va_bits`位的地址按第`va_bits - 1`位符号扩展为规范地址
+#[inline]
+pub const fn sign_extend(addr: usize, va_bits: usize) -> usize {
+    let low = addr & mask_bits!(va_bits);
+    if low & (1usize << (va_bits - 1)) != 0 {
+        low | !mask_bits!(va_bits)
+    } else {
+        low
+    }
+}
+
+/// 内核运行的特权级，决定内核窗口位于地址空间的哪一半
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelLevel {
+    /// 内核窗口在高半部分（TTBR1），`mdb_node`中的指针需要按最高位符号扩展
+    EL1,
+    /// 内核窗口在低半部分（只有TTBR0），从`EL2_WINDOW_BASE`开始
+    EL2,
+}
+
+/// EL2内核窗口的基址
+///
+/// 这一基址由内核的内存布局（EL2下的`PPTR_BASE`）决定，与`va_bits`无关，
+/// 假设内核窗口位于`[EL2_WINDOW_BASE, 2 * EL2_WINDOW_BASE)`，`va_bits`只需要足够容纳它。
+pub const EL2_WINDOW_BASE: usize = 0x80_0000_0000;
+
+/// `mdb_node`中保存的内核指针所在的虚拟地址窗口
+///
+/// `mdbNext`和`mdbPrev`都只保存指针的低`va_bits`位，`encode`和`decode`互为逆操作，
+/// 窗口内的指针经过一次编码和解码后保持不变。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelWindow {
+    pub va_bits: usize,
+    pub level: KernelLevel,
+}
+
+impl KernelWindow {
+    /// 将内核地址编码为写入`mdb_node`的值，只保留低`va_bits`位
+    #[inline]
+    pub const fn encode(&self, addr: usize) -> usize {
+        addr & mask_bits!(self.va_bits)
+    }
+
+    /// 从`mdb_node`的原始字中恢复内核地址，低2位的标志位会被忽略
+    ///
+    /// EL2与原来的实现一致：第`va_bits - 2`位置位时补上`EL2_WINDOW_BASE`。
+    /// 按`EL2_WINDOW_BASE`上的假设，窗口内的地址本身已经带有这一位，所以这不影响窗口内的指针。
+    #[inline]
+    pub const fn decode(&self, raw: usize) -> usize {
+        let value = raw & mask_bits!(self.va_bits) & !0b11;
+        match self.level {
+            KernelLevel::EL1 => sign_extend(value, self.va_bits),
+            KernelLevel::EL2 if value & (1usize << (self.va_bits - 2)) != 0 => {
+                value | EL2_WINDOW_BASE
+            }
+            KernelLevel::EL2 => value,
+        }
+    }
+
+    /// `addr`写入`mdb_node`后能否被`decode`原样恢复，空指针总是可以
+    #[inline]
+    pub const fn contains(&self, addr: usize) -> bool {
+        addr & 0b11 == 0 && self.decode(self.encode(addr)) == addr
+    }
+}
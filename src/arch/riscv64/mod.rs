@@ -7,12 +7,40 @@ use sel4_common::{
     vm_rights::vm_rights_from_word,
 };
 
+use super::{report_unknown_arch_cap, sign_extend};
 use crate::{
     capability::{cap_arch_func, zombie::zombie_func},
     cte::deriveCap_ret,
     interface::cte_t,
 };
 
+/// 内核虚拟地址宽度，默认为Sv39，`riscv_sv48`和`riscv_sv57`选择更宽的页表模式，同时开启时以Sv57为准
+#[cfg(not(any(feature = "riscv_sv48", feature = "riscv_sv57")))]
+pub const VA_BITS: usize = 39;
+#[cfg(all(feature = "riscv_sv48", not(feature = "riscv_sv57")))]
+pub const VA_BITS: usize = 48;
+#[cfg(feature = "riscv_sv57")]
+pub const VA_BITS: usize = 57;
+
+/// 将只保留了低`VA_BITS`位的地址按第`VA_BITS - 1`位符号扩展为规范地址
+#[inline]
+pub const fn canonical_address(addr: usize) -> usize {
+    sign_extend(addr, VA_BITS)
+}
+
+/// `mdb_node`中保存的是指针的低`VA_BITS`位。
+/// `sel4_common`的位域按Sv39生成，更宽的`mdbNext`会用到其上方的填充位，所以`mdb_node`中的指针都通过这一对函数读写
+#[inline]
+pub const fn encode_mdb_ptr(addr: usize) -> usize {
+    addr & mask_bits!(VA_BITS)
+}
+
+/// 从`mdb_node`中的原始值恢复指针，低2位的标志位会被忽略
+#[inline]
+pub const fn decode_mdb_ptr(raw: usize) -> usize {
+    canonical_address(raw & !0b11)
+}
+
 impl cap_arch_func for cap {
     fn arch_updatedata(&self, _preserve: bool, _new_data: u64) -> Self {
         return self.clone();
@@ -84,7 +112,8 @@ impl cte_t {
                 ret.capability = capability.clone();
             }
             _ => {
-                panic!(" Invalid arch cap type : {}", capability.get_tag() as usize);
+                report_unknown_arch_cap("arch_derive_cap", capability.get_tag());
+                ret.status = exception_t::EXCEPTION_SYSCALL_ERROR;
             }
         }
         ret
@@ -147,7 +176,7 @@ pub fn arch_same_region_as(cap1: &cap, cap2: &cap) -> bool {
                     == cap::cap_asid_pool_cap(cap2).get_capASIDPool();
             }
         }
-        _ => panic!("unknown cap"),
+        _ => report_unknown_arch_cap("arch_same_region_as", cap1.get_tag()),
     }
     false
 }
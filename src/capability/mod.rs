@@ -11,13 +11,22 @@
 //! ```
 //! Represent a capability, composed by two words. Different cap can contain different bit fields.
 
+pub mod typed;
 pub mod zombie;
 
 use sel4_common::sel4_config::*;
+use sel4_common::shared_types_bf_gen::seL4_CapRights;
 use sel4_common::structures_gen::{cap, cap_null_cap, cap_tag};
 
-use crate::arch::{arch_same_object_as, arch_same_region_as};
+#[cfg(target_arch = "loongarch64")]
+use crate::arch::PD_SIZE_BITS;
+#[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+use crate::arch::VCPU_SIZE_BITS;
+use crate::arch::{arch_mask_cap_rights, arch_same_object_as, arch_same_region_as};
+#[cfg(target_arch = "x86_64")]
+use crate::arch::{PDPT_SIZE_BITS, PD_SIZE_BITS, PML4_SIZE_BITS};
 
+/// `seL4_CNode_CapData`，低`WORD_RADIX`位为`guardSize`，其余高位为`guard`
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct CNodeCapData {
@@ -32,12 +41,12 @@ impl CNodeCapData {
 
     #[inline]
     pub fn get_guard(&self) -> usize {
-        (self.words[0] & 0xffffffffffffffc0usize) >> 6
+        self.words[0] >> WORD_RADIX
     }
 
     #[inline]
     pub fn get_guard_size(&self) -> usize {
-        self.words[0] & 0x3fusize
+        self.words[0] & mask_bits!(WORD_RADIX)
     }
 }
 
@@ -107,6 +116,14 @@ impl cap_func for cap {
                 cap::cap_cnode_cap(self).get_capCNodeRadix() as usize + SEL4_SLOT_BITS
             }
             cap_tag::cap_page_table_cap => PT_SIZE_BITS,
+            #[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+            cap_tag::cap_vcpu_cap => VCPU_SIZE_BITS,
+            #[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+            cap_tag::cap_page_directory_cap => PD_SIZE_BITS,
+            #[cfg(target_arch = "x86_64")]
+            cap_tag::cap_pdpt_cap => PDPT_SIZE_BITS,
+            #[cfg(target_arch = "x86_64")]
+            cap_tag::cap_pml4_cap => PML4_SIZE_BITS,
             #[cfg(feature = "kernel_mcs")]
             cap_tag::cap_reply_cap => SEL4_REPLY_BITS,
             #[cfg(not(feature = "kernel_mcs"))]
@@ -124,6 +141,21 @@ impl cap_func for cap {
         if self.get_tag() == cap_tag::cap_vspace_cap {
             return true;
         }
+        #[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+        if self.get_tag() == cap_tag::cap_vcpu_cap {
+            return true;
+        }
+        #[cfg(target_arch = "loongarch64")]
+        if self.get_tag() == cap_tag::cap_page_directory_cap {
+            return true;
+        }
+        #[cfg(target_arch = "x86_64")]
+        if matches!(
+            self.get_tag(),
+            cap_tag::cap_page_directory_cap | cap_tag::cap_pdpt_cap | cap_tag::cap_pml4_cap
+        ) {
+            return true;
+        }
         #[cfg(not(feature = "kernel_mcs"))]
         {
             matches!(
@@ -297,3 +329,31 @@ pub fn is_cap_revocable(derived_cap: &cap, src_cap: &cap) -> bool {
         _ => false,
     }
 }
+
+/// 按`rights`削减`capability`的权限，返回新的`cap`，arch cap交给`arch_mask_cap_rights`处理
+pub fn mask_cap_rights(rights: seL4_CapRights, capability: &cap) -> cap {
+    if capability.is_arch_cap() {
+        return arch_mask_cap_rights(rights, capability);
+    }
+    let new_cap = capability.clone();
+    match capability.get_tag() {
+        cap_tag::cap_endpoint_cap => {
+            let ep = cap::cap_endpoint_cap(&new_cap);
+            ep.set_capCanSend(ep.get_capCanSend() & rights.get_capAllowWrite());
+            ep.set_capCanReceive(ep.get_capCanReceive() & rights.get_capAllowRead());
+            ep.set_capCanGrant(ep.get_capCanGrant() & rights.get_capAllowGrant());
+            ep.set_capCanGrantReply(ep.get_capCanGrantReply() & rights.get_capAllowGrantReply());
+        }
+        cap_tag::cap_notification_cap => {
+            let ntfn = cap::cap_notification_cap(&new_cap);
+            ntfn.set_capNtfnCanSend(ntfn.get_capNtfnCanSend() & rights.get_capAllowWrite());
+            ntfn.set_capNtfnCanReceive(ntfn.get_capNtfnCanReceive() & rights.get_capAllowRead());
+        }
+        cap_tag::cap_reply_cap => {
+            let reply = cap::cap_reply_cap(&new_cap);
+            reply.set_capReplyCanGrant(reply.get_capReplyCanGrant() & rights.get_capAllowGrant());
+        }
+        _ => {}
+    }
+    new_cap
+}
@@ -0,0 +1,185 @@
+//! 带类型的`cap`封装
+//!
+//! 直接调用`cap::cap_endpoint_cap(&c)`之类的访问函数时不会检查`cap_tag`，用错了访问函数只会把位域解释错。
+//! `Cap<T>`在构造时检查一次`cap_tag`，之后的字段访问都不需要再按`cap_tag`分派：
+//!
+//! ```ignore
+//! let ep = Cap::<Endpoint>::try_from(&slot.capability)?;
+//! if ep.badge() == 0 { ... }
+//! ```
+use core::marker::PhantomData;
+
+use sel4_common::shared_types_bf_gen::seL4_CapRights;
+use sel4_common::structures_gen::{cap, cap_tag};
+
+use super::cap_arch_func;
+
+/// `Cap<T>`的类型参数，`TAG`为对应的`cap_tag`
+pub trait CapType {
+    const TAG: u64;
+}
+
+macro_rules! cap_type {
+    ($(#[$meta:meta])* $name:ident, $tag:ident) => {
+        $(#[$meta])*
+        pub struct $name;
+
+        impl CapType for $name {
+            const TAG: u64 = cap_tag::$tag;
+        }
+    };
+}
+
+cap_type!(
+    /// `endpoint_cap`
+    Endpoint,
+    cap_endpoint_cap
+);
+cap_type!(
+    /// `notification_cap`
+    Notification,
+    cap_notification_cap
+);
+cap_type!(
+    /// `cnode_cap`
+    CNode,
+    cap_cnode_cap
+);
+cap_type!(
+    /// `untyped_cap`
+    Untyped,
+    cap_untyped_cap
+);
+cap_type!(
+    /// `thread_cap`
+    Thread,
+    cap_thread_cap
+);
+
+/// `cap_tag`与目标类型不符
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WrongCapType {
+    pub expected: u64,
+    pub found: u64,
+}
+
+/// 已经检查过`cap_tag`的`cap`
+pub struct Cap<T: CapType> {
+    raw: cap,
+    _type: PhantomData<T>,
+}
+
+impl<T: CapType> Clone for Cap<T> {
+    fn clone(&self) -> Self {
+        Cap {
+            raw: self.raw.clone(),
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T: CapType> TryFrom<&cap> for Cap<T> {
+    type Error = WrongCapType;
+
+    fn try_from(capability: &cap) -> Result<Self, WrongCapType> {
+        let found = capability.get_tag();
+        if found != T::TAG {
+            return Err(WrongCapType {
+                expected: T::TAG,
+                found,
+            });
+        }
+        Ok(Cap {
+            raw: capability.clone(),
+            _type: PhantomData,
+        })
+    }
+}
+
+impl<T: CapType> Cap<T> {
+    #[inline]
+    pub fn raw(&self) -> &cap {
+        &self.raw
+    }
+
+    #[inline]
+    pub fn into_raw(self) -> cap {
+        self.raw
+    }
+
+    /// `cap`指向的内核对象地址
+    #[inline]
+    pub fn ptr(&self) -> usize {
+        self.raw.get_cap_ptr()
+    }
+}
+
+impl Cap<Endpoint> {
+    #[inline]
+    pub fn badge(&self) -> u64 {
+        cap::cap_endpoint_cap(&self.raw).get_capEPBadge()
+    }
+
+    pub fn rights(&self) -> seL4_CapRights {
+        let ep = cap::cap_endpoint_cap(&self.raw);
+        seL4_CapRights::new(
+            ep.get_capCanGrantReply(),
+            ep.get_capCanGrant(),
+            ep.get_capCanReceive(),
+            ep.get_capCanSend(),
+        )
+    }
+}
+
+impl Cap<Notification> {
+    #[inline]
+    pub fn badge(&self) -> u64 {
+        cap::cap_notification_cap(&self.raw).get_capNtfnBadge()
+    }
+
+    /// notification没有grant权限，读写分别对应`CanReceive`和`CanSend`
+    pub fn rights(&self) -> seL4_CapRights {
+        let ntfn = cap::cap_notification_cap(&self.raw);
+        seL4_CapRights::new(
+            0,
+            0,
+            ntfn.get_capNtfnCanReceive(),
+            ntfn.get_capNtfnCanSend(),
+        )
+    }
+}
+
+impl Cap<CNode> {
+    #[inline]
+    pub fn guard(&self) -> u64 {
+        cap::cap_cnode_cap(&self.raw).get_capCNodeGuard()
+    }
+
+    #[inline]
+    pub fn guard_size(&self) -> u64 {
+        cap::cap_cnode_cap(&self.raw).get_capCNodeGuardSize()
+    }
+
+    #[inline]
+    pub fn radix(&self) -> u64 {
+        cap::cap_cnode_cap(&self.raw).get_capCNodeRadix()
+    }
+}
+
+impl Cap<Untyped> {
+    /// 已分配部分的大小，单位为`2^seL4_MinUntypedBits`字节
+    #[inline]
+    pub fn free_index(&self) -> u64 {
+        cap::cap_untyped_cap(&self.raw).get_capFreeIndex()
+    }
+
+    #[inline]
+    pub fn block_size(&self) -> u64 {
+        cap::cap_untyped_cap(&self.raw).get_capBlockSize()
+    }
+
+    #[inline]
+    pub fn is_device(&self) -> bool {
+        cap::cap_untyped_cap(&self.raw).get_capIsDevice() != 0
+    }
+}
@@ -1,7 +1,7 @@
 //! zombie cap相关字段和方法
 //! 当`tcb_cap`和`cnode_cap`删除的过程中会变为`zombie_cap`
 use crate::cte::cte_t;
-use sel4_common::sel4_config::WORD_RADIX;
+use sel4_common::sel4_config::{WORD_BITS, WORD_RADIX};
 use sel4_common::structures_gen::{cap, cap_tag, cap_zombie_cap};
 
 /// Judge whether the zombie cap is from tcb cap.
@@ -27,34 +27,43 @@ impl zombie_func for cap_zombie_cap {
 
     #[inline]
     fn get_zombie_ptr(&self) -> usize {
-        let radix = self.get_zombie_bit();
-        self.get_capZombieID() as usize & !mask_bits!(radix + 1)
+        self.get_capZombieID() as usize & !zombie_id_mask(self.get_zombie_bit())
     }
 
     #[inline]
     fn get_zombie_number(&self) -> usize {
-        let radix = self.get_zombie_bit();
-        self.get_capZombieID() as usize & mask_bits!(radix + 1)
+        self.get_capZombieID() as usize & zombie_id_mask(self.get_zombie_bit())
     }
 
     #[inline]
     fn set_zombie_number(&mut self, n: usize) {
-        let radix = self.get_zombie_bit();
-        let ptr = self.get_capZombieID() as usize & !mask_bits!(radix + 1);
-        self.set_capZombieID((ptr | (n & mask_bits!(radix + 1))) as u64);
+        let mask = zombie_id_mask(self.get_zombie_bit());
+        let ptr = self.get_capZombieID() as usize & !mask;
+        self.set_capZombieID((ptr | (n & mask)) as u64);
     }
 }
 
 #[inline]
 pub fn zombie_new(number: usize, _type: usize, ptr: usize) -> cap {
     let mask = if _type == ZOMBIE_TYPE_ZOMBIE_TCB {
-        mask_bits!(TCB_CNODE_RADIX + 1)
+        zombie_id_mask(TCB_CNODE_RADIX)
     } else {
-        mask_bits!(_type + 1)
+        zombie_id_mask(_type)
     };
     cap_zombie_cap::new(((ptr & !mask) | (number & mask)) as u64, _type as u64).unsplay()
 }
 
+/// `capZombieID`中低`radix + 1`位保存编号，其余位保存对象地址。
+/// 32位下`radix`可以达到`WORD_BITS - 1`，此时整个字都用来保存编号
+#[inline]
+fn zombie_id_mask(radix: usize) -> usize {
+    if radix + 1 >= WORD_BITS {
+        usize::MAX
+    } else {
+        mask_bits!(radix + 1)
+    }
+}
+
 pub fn zombie_type_zombie_cnode(n: usize) -> usize {
     n & mask_bits!(WORD_RADIX)
 }
@@ -1,9 +1,13 @@
 //! `CSpace Table Entry`相关操作的具体实现，包含`cte`链表的插入删除等。
 use super::{
     capability::{is_cap_revocable, same_object_as, same_region_as},
-    deps::{finalise_cap, post_cap_deletion, preemption_point},
-    structures::{finaliseSlot_ret, resolveAddressBits_ret_t},
+    deps::{CSpaceHooks, KernelHooks},
+    lock::{assert_locked, neighbours, MdbGuard, NoLock},
+    structures::{
+        finaliseSlot_ret, resolveAddressBits_ret_t, DeleteProgress, LookupFault, RevokeProgress,
+    },
 };
+use crate::arch::{decode_mdb_ptr, encode_mdb_ptr};
 use crate::capability::{
     cap_func,
     zombie::{cap_cyclic_zombie, zombie_func},
@@ -38,6 +42,72 @@ pub struct cte_t {
     pub cteMDBNode: mdb_node,
 }
 
+/// `mdb_node`中前后节点指针的读写
+///
+/// 指针经过`arch::encode_mdb_ptr`写入，经过`arch::decode_mdb_ptr`读出。
+/// 64位架构上`mdbNext`的宽度由架构的虚拟地址宽度决定，直接读写`mdb_node`的第二个字，
+/// 不经过`sel4_common`中按固定宽度生成的`get_mdbNext`和`set_mdbNext`。
+pub(crate) trait mdb_ptr_func {
+    fn get_next_ptr(&self) -> usize;
+    fn get_prev_ptr(&self) -> usize;
+    fn set_next_ptr(&mut self, addr: usize);
+    fn set_prev_ptr(&mut self, addr: usize);
+}
+
+/// `mdbNext`所在的字，低2位是`mdbRevocable`和`mdbFirstBadged`
+#[cfg(target_pointer_width = "64")]
+const MDB_NEXT_WORD: usize = 1;
+
+/// 编码`addr`，并检查它能被原样读回
+#[inline]
+fn encode_checked(addr: usize) -> u64 {
+    let raw = encode_mdb_ptr(addr);
+    debug_assert_eq!(
+        decode_mdb_ptr(raw),
+        addr,
+        "{:#x} cannot be stored in mdb_node",
+        addr
+    );
+    raw as u64
+}
+
+impl mdb_ptr_func for mdb_node {
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn get_next_ptr(&self) -> usize {
+        decode_mdb_ptr(self.0.arr[MDB_NEXT_WORD] as usize)
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn get_next_ptr(&self) -> usize {
+        decode_mdb_ptr(self.get_mdbNext() as usize)
+    }
+
+    #[inline]
+    fn get_prev_ptr(&self) -> usize {
+        decode_mdb_ptr(self.get_mdbPrev() as usize)
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    fn set_next_ptr(&mut self, addr: usize) {
+        let word = &mut self.0.arr[MDB_NEXT_WORD];
+        *word = (*word & 0b11) | encode_checked(addr);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    fn set_next_ptr(&mut self, addr: usize) {
+        self.set_mdbNext(encode_checked(addr));
+    }
+
+    #[inline]
+    fn set_prev_ptr(&mut self, addr: usize) {
+        self.set_mdbPrev(encode_checked(addr));
+    }
+}
+
 impl cte_t {
     pub fn get_ptr(&self) -> usize {
         self as *const cte_t as usize
@@ -83,16 +153,13 @@ impl cte_t {
     }
     /// 判断当前`cte`是否存在派生出来的子节点
     pub fn ensure_no_children(&self) -> exception_t {
-        if self.cteMDBNode.get_mdbNext() != 0 {
-            let next = convert_to_type_ref::<cte_t>(self.cteMDBNode.get_mdbNext() as usize);
-            if self.is_mdb_parent_of(next) {
-                return exception_t::EXCEPTION_SYSCALL_ERROR;
-            }
+        if self.descendants().next().is_some() {
+            return exception_t::EXCEPTION_SYSCALL_ERROR;
         }
         exception_t::EXCEPTION_NONE
     }
     /// 判断当前`cte`是否为`next`节点的父节点（除了父节点，还有兄弟节点的关系可能）
-    fn is_mdb_parent_of(&self, next: &Self) -> bool {
+    pub(crate) fn is_mdb_parent_of(&self, next: &Self) -> bool {
         if self.cteMDBNode.get_mdbRevocable() == 0 {
             return false;
         }
@@ -135,20 +202,20 @@ impl cte_t {
     /// 如果`cte`的`next`是当前`cte`派生出来的能力，则当前`cte`也不是最后一个`cap`
     pub fn is_final_cap(&self) -> bool {
         let mdb = &self.cteMDBNode;
-        let prev_is_same_obj = if mdb.get_mdbPrev() == 0 {
+        let prev_is_same_obj = if mdb.get_prev_ptr() == 0 {
             false
         } else {
-            let prev = convert_to_type_ref::<cte_t>(mdb.get_mdbPrev() as usize);
+            let prev = convert_to_type_ref::<cte_t>(mdb.get_prev_ptr());
             same_object_as(&prev.capability, &self.capability)
         };
 
         if prev_is_same_obj {
             return false;
         }
-        if mdb.get_mdbNext() == 0 {
+        if mdb.get_next_ptr() == 0 {
             true
         } else {
-            let next = convert_to_type_ref::<cte_t>(mdb.get_mdbNext() as usize);
+            let next = convert_to_type_ref::<cte_t>(mdb.get_next_ptr());
             !same_object_as(&self.capability, &next.capability)
         }
     }
@@ -172,24 +239,35 @@ impl cte_t {
     /// 之后再次进入`reduce_zombie(false)`，在其中进入`else`分支，
     /// 执行`cteswap`将二级`cnode_cap`中的第一个`cap`与二级`cnode_cap`进行交换，使得二级`cnode_cap`指向自身，变成`cyclicZombie`。
     /// 然后继续清除即可。至于二级`cnode_cap`其实无法被清除。
-    unsafe fn finalise(&mut self, immediate: bool) -> finaliseSlot_ret {
+    ///
+    /// `deleted`累计被清空的`slot`数量
+    fn finalise<H: CSpaceHooks, G: MdbGuard>(
+        &mut self,
+        guard: &G,
+        immediate: bool,
+        deleted: &mut usize,
+    ) -> finaliseSlot_ret {
         let mut ret = finaliseSlot_ret::default();
         while self.capability.get_tag() != cap_tag::cap_null_cap {
-            let fc_ret = finalise_cap(&self.capability, self.is_final_cap(), false);
+            let fc_ret = H::finalise_cap(&self.capability, self.is_final_cap(), false);
             if cap_removable(&fc_ret.remainder, self) {
                 ret.status = exception_t::EXCEPTION_NONE;
                 ret.success = true;
                 ret.cleanupInfo = fc_ret.cleanupInfo;
                 return ret;
             }
-            self.capability = fc_ret.clone().remainder;
+            cnode_slot_changed(&self.capability);
+            {
+                let _write = slots_changing([self.get_ptr()]);
+                self.capability = fc_ret.clone().remainder;
+            }
             if !immediate && cap_cyclic_zombie(&fc_ret.remainder, self) {
                 ret.status = exception_t::EXCEPTION_NONE;
                 ret.success = false;
                 ret.cleanupInfo = fc_ret.cleanupInfo;
                 return ret;
             }
-            let status = self.reduce_zombie(immediate);
+            let status = self.reduce_zombie::<H, G>(guard, immediate, deleted);
             if exception_t::EXCEPTION_NONE != status {
                 ret.status = status;
                 ret.success = false;
@@ -197,7 +275,7 @@ impl cte_t {
                 return ret;
             }
 
-            let status = preemption_point();
+            let status = H::preemption_point();
             if exception_t::EXCEPTION_NONE != status {
                 ret.status = status;
                 ret.success = false;
@@ -211,56 +289,107 @@ impl cte_t {
     /// 将当前的`cte slot`中的能力清除，因为可能是`cnode_cap`或者`tcb_cap`，其中都可以存储多个`cap`，
     /// 所以可能顺带将存储的`cap`也清除掉
     pub fn delete_all(&mut self, exposed: bool) -> exception_t {
-        let fs_ret = unsafe { self.finalise(exposed) };
+        self.delete_all_with::<KernelHooks, _>(&NoLock, exposed)
+            .status
+    }
+
+    /// 同`delete_all`，内核回调由`H`提供，每个被清空的`slot`及其前后节点都需要受`guard`保护，返回本次调用的进度
+    ///
+    /// 没有完成时内核可以再次对`in_progress`调用以继续删除
+    pub fn delete_all_with<H: CSpaceHooks, G: MdbGuard>(
+        &mut self,
+        guard: &G,
+        exposed: bool,
+    ) -> DeleteProgress {
+        let mut progress = DeleteProgress::default();
+        progress.status =
+            self.delete_all_counted::<H, G>(guard, exposed, &mut progress.slots_deleted);
+        if progress.status != exception_t::EXCEPTION_NONE
+            || self.capability.get_tag() != cap_tag::cap_null_cap
+        {
+            progress.in_progress = self.get_ptr();
+        }
+        progress
+    }
+
+    fn delete_all_counted<H: CSpaceHooks, G: MdbGuard>(
+        &mut self,
+        guard: &G,
+        exposed: bool,
+        deleted: &mut usize,
+    ) -> exception_t {
+        let fs_ret = self.finalise::<H, G>(guard, exposed, deleted);
         if fs_ret.status != exception_t::EXCEPTION_NONE {
             return fs_ret.status;
         }
         if exposed || fs_ret.success {
-            self.set_empty(&fs_ret.cleanupInfo);
+            self.set_empty::<H, G>(guard, &fs_ret.cleanupInfo, deleted);
         }
         exception_t::EXCEPTION_NONE
     }
 
     /// 将当前的`cte slot`中的能力清除,要求`cap`是可删除的
     pub fn delete_one(&mut self) {
+        self.delete_one_with::<KernelHooks, _>(&NoLock)
+    }
+
+    /// 同`delete_one`，内核回调由`H`提供，当前`slot`及其前后节点都需要受`guard`保护
+    pub fn delete_one_with<H: CSpaceHooks, G: MdbGuard>(&mut self, guard: &G) {
         if self.capability.get_tag() != cap_tag::cap_null_cap {
-            let fc_ret = unsafe { finalise_cap(&self.capability, self.is_final_cap(), true) };
+            let fc_ret = H::finalise_cap(&self.capability, self.is_final_cap(), true);
             assert!(
                 cap_removable(&fc_ret.remainder, self)
                     && fc_ret.cleanupInfo.get_tag() == cap_tag::cap_null_cap
             );
-            self.set_empty(&cap_null_cap::new().unsplay());
+            self.set_empty::<H, G>(guard, &cap_null_cap::new().unsplay(), &mut 0);
         }
     }
 
     /// 将当前`slot`从`capability derivation tree`中删除
-    fn set_empty(&mut self, cleanup_info: &cap) {
+    fn set_empty<H: CSpaceHooks, G: MdbGuard>(
+        &mut self,
+        guard: &G,
+        cleanup_info: &cap,
+        deleted: &mut usize,
+    ) {
         if self.capability.get_tag() != cap_tag::cap_null_cap {
+            let [prev, next] = neighbours(self);
+            assert_locked(guard, &[self.get_ptr(), prev, next]);
+            cnode_slot_changed(&self.capability);
             let mdb = &self.cteMDBNode;
-            let prev_addr = mdb.get_mdbPrev() as usize;
-            let next_addr = mdb.get_mdbNext() as usize;
+            let prev_addr = mdb.get_prev_ptr();
+            let next_addr = mdb.get_next_ptr();
             if prev_addr != 0 {
                 let prev_node = convert_to_mut_type_ref::<cte_t>(prev_addr);
-                prev_node.cteMDBNode.set_mdbNext(next_addr as u64);
+                prev_node.cteMDBNode.set_next_ptr(next_addr);
             }
 
             if next_addr != 0 {
                 let next_node = convert_to_mut_type_ref::<cte_t>(next_addr);
-                next_node.cteMDBNode.set_mdbPrev(prev_addr as u64);
+                next_node.cteMDBNode.set_prev_ptr(prev_addr);
                 let first_badged = ((next_node.cteMDBNode.get_mdbFirstBadged() != 0)
                     || (mdb.get_mdbFirstBadged() != 0)) as usize;
                 next_node.cteMDBNode.set_mdbFirstBadged(first_badged as u64);
             }
-            self.capability = cap_null_cap::new().unsplay();
+            {
+                let _write = slots_changing([self.get_ptr()]);
+                self.capability = cap_null_cap::new().unsplay();
+            }
             self.cteMDBNode = mdb_node {
                 0: Bitfield { arr: [0; 2usize] },
             };
-            unsafe { post_cap_deletion(cleanup_info) };
+            H::post_cap_deletion(cleanup_info);
+            *deleted += 1;
         }
     }
 
     /// 每次删除`zombie cap`中的最后一个`capability`,用于删除unremovable的capability。
-    fn reduce_zombie(&mut self, immediate: bool) -> exception_t {
+    fn reduce_zombie<H: CSpaceHooks, G: MdbGuard>(
+        &mut self,
+        guard: &G,
+        immediate: bool,
+        deleted: &mut usize,
+    ) -> exception_t {
         assert_eq!(self.capability.get_tag(), cap_tag::cap_zombie_cap);
         let self_ptr = self as *mut cte_t as usize;
         let ptr = cap::cap_zombie_cap(&self.capability).get_zombie_ptr();
@@ -269,7 +398,7 @@ impl cte_t {
         assert!(n > 0);
         if immediate {
             let end_slot = unsafe { &mut *((ptr as *mut cte_t).add(n - 1)) };
-            let status = end_slot.delete_all(false);
+            let status = end_slot.delete_all_counted::<H, G>(guard, false, deleted);
             if status != exception_t::EXCEPTION_NONE {
                 return status;
             }
@@ -284,6 +413,7 @@ impl cte_t {
                         && cap::cap_zombie_cap(&self.capability).get_capZombieType() == zombie_type
                     {
                         assert_eq!(end_slot.capability.get_tag(), cap_tag::cap_null_cap);
+                        let _write = slots_changing([self_ptr]);
                         cap::cap_zombie_cap(&self.capability).set_zombie_number(n - 1);
                     } else {
                         assert!(ptr2 == self_ptr && ptr != self_ptr);
@@ -298,63 +428,56 @@ impl cte_t {
             let next_slot = convert_to_mut_type_ref::<cte_t>(ptr);
             let cap1 = next_slot.capability.clone();
             let cap2 = self.capability.clone();
-            cte_swap(&cap1, next_slot, &cap2, self);
+            cte_swap_locked(guard, &cap1, next_slot, &cap2, self);
         }
         exception_t::EXCEPTION_NONE
     }
 
-    #[cfg(target_arch = "riscv64")]
-    #[inline]
-    fn get_volatile_value(&self) -> usize {
-        unsafe {
-            let raw_value = ptr::read_volatile((self.get_ptr() + 24) as *const usize);
-            let mut value = ((raw_value >> 2) & mask_bits!(37)) << 2;
-            if (value & (1usize << 38)) != 0 {
-                value |= 0xffffff8000000000;
-            }
-            value
-        }
-    }
-
-    #[cfg(target_arch = "aarch64")]
+    /// 以volatile方式读取`mdbNext`，其他核可能正在修改派生树
     #[inline]
-    fn get_volatile_value(&self) -> usize {
-        unsafe {
-            let raw_value = ptr::read_volatile((self.get_ptr() + 24) as *const usize);
-            let mut value = ((raw_value >> 2) & mask_bits!(46)) << 2;
-            if (value & (1usize << 46)) != 0 {
-                #[cfg(not(feature = "hypervisor"))]
-                {
-                    value |= 0xffffff8000000000;
-                }
-                #[cfg(feature = "hypervisor")]
-                {
-                    value |= 0x8000000000;
-                }
-            }
-            value
-        }
+    pub(crate) fn get_volatile_value(&self) -> usize {
+        unsafe { ptr::read_volatile(&self.cteMDBNode) }.get_next_ptr()
     }
 
     // 撤销当前`cte`中的`capability`
     #[inline]
     pub fn revoke(&mut self) -> exception_t {
+        self.revoke_with::<KernelHooks, _>(&NoLock).status
+    }
+
+    /// 同`revoke`，内核回调由`H`提供，当前`slot`和每个被删除的子节点及其前后节点都需要受`guard`保护，
+    /// 返回本次调用的进度
+    ///
+    /// 没有完成时内核可以再次调用以继续撤销
+    pub fn revoke_with<H: CSpaceHooks, G: MdbGuard>(&mut self, guard: &G) -> RevokeProgress {
+        assert_locked(guard, &[self.get_ptr()]);
+        let mut progress = RevokeProgress::default();
         while let Some(cte) = convert_to_option_mut_type_ref::<cte_t>(self.get_volatile_value()) {
             if !self.is_mdb_parent_of(cte) {
                 break;
             }
 
-            let mut status = cte.delete_all(true);
+            let status = cte.delete_all_counted::<H, G>(guard, true, &mut progress.slots_deleted);
             if status != exception_t::EXCEPTION_NONE {
-                return status;
+                progress.status = status;
+                progress.in_progress = cte.get_ptr();
+                return progress;
             }
 
-            status = unsafe { preemption_point() };
+            let status = H::preemption_point();
             if status != exception_t::EXCEPTION_NONE {
-                return status;
+                progress.status = status;
+                if let Some(next) =
+                    convert_to_option_mut_type_ref::<cte_t>(self.get_volatile_value())
+                {
+                    if self.is_mdb_parent_of(next) {
+                        progress.in_progress = next.get_ptr();
+                    }
+                }
+                return progress;
             }
         }
-        exception_t::EXCEPTION_NONE
+        progress
     }
 }
 
@@ -362,114 +485,273 @@ impl cte_t {
 ///
 /// 将一个new_cap插入到dest slot中并作为src slot的派生子节点插入派生树中
 pub fn cte_insert(new_cap: &cap, src_slot: &mut cte_t, dest_slot: &mut cte_t) {
+    cte_insert_locked(&NoLock, new_cap, src_slot, dest_slot)
+}
+
+/// 同`cte_insert`，`src_slot`、`dest_slot`和`src_slot`的后继节点需要受`guard`保护
+pub fn cte_insert_locked<G: MdbGuard>(
+    guard: &G,
+    new_cap: &cap,
+    src_slot: &mut cte_t,
+    dest_slot: &mut cte_t,
+) {
+    let [_, next] = neighbours(src_slot);
+    assert_locked(guard, &[src_slot.get_ptr(), dest_slot.get_ptr(), next]);
     let srcMDB = &mut src_slot.cteMDBNode;
     let srcCap = &(src_slot.capability.clone());
     let mut newMDB = srcMDB.clone();
     let newCapIsRevocable = is_cap_revocable(new_cap, srcCap);
-    newMDB.set_mdbPrev(src_slot as *const cte_t as u64);
+    newMDB.set_prev_ptr(src_slot as *const cte_t as usize);
     newMDB.set_mdbRevocable(newCapIsRevocable as u64);
     newMDB.set_mdbFirstBadged(newCapIsRevocable as u64);
 
     /* Haskell error: "cteInsert to non-empty destination" */
     assert_eq!(dest_slot.capability.get_tag(), cap_tag::cap_null_cap);
     /* Haskell error: "cteInsert: mdb entry must be empty" */
-    assert!(dest_slot.cteMDBNode.get_mdbNext() == 0 && dest_slot.cteMDBNode.get_mdbPrev() == 0);
+    assert!(dest_slot.cteMDBNode.get_next_ptr() == 0 && dest_slot.cteMDBNode.get_prev_ptr() == 0);
 
+    let _write = slots_changing([src_slot.get_ptr(), dest_slot.get_ptr()]);
     set_untyped_cap_as_full(srcCap, new_cap, src_slot);
+    cnode_slot_changed(new_cap);
 
     dest_slot.capability = new_cap.clone();
     dest_slot.cteMDBNode = newMDB.clone();
     src_slot
         .cteMDBNode
-        .set_mdbNext(dest_slot as *const cte_t as u64);
-    if newMDB.get_mdbNext() != 0 {
-        let cte_ref = convert_to_mut_type_ref::<cte_t>(newMDB.get_mdbNext() as usize);
+        .set_next_ptr(dest_slot as *const cte_t as usize);
+    if newMDB.get_next_ptr() != 0 {
+        let cte_ref = convert_to_mut_type_ref::<cte_t>(newMDB.get_next_ptr());
         cte_ref
             .cteMDBNode
-            .set_mdbPrev(dest_slot as *const cte_t as u64);
+            .set_prev_ptr(dest_slot as *const cte_t as usize);
     }
 }
 
 /// insert a new cap to slot, set parent's next is slot.
 pub fn insert_new_cap(parent: &mut cte_t, slot: &mut cte_t, capability: &cap) {
-    let next = parent.cteMDBNode.get_mdbNext() as usize;
+    insert_new_cap_locked(&NoLock, parent, slot, capability)
+}
+
+/// 同`insert_new_cap`，`parent`、`slot`和`parent`的后继节点需要受`guard`保护
+pub fn insert_new_cap_locked<G: MdbGuard>(
+    guard: &G,
+    parent: &mut cte_t,
+    slot: &mut cte_t,
+    capability: &cap,
+) {
+    let [_, next] = neighbours(parent);
+    assert_locked(guard, &[parent.get_ptr(), slot.get_ptr(), next]);
+    let next = parent.cteMDBNode.get_next_ptr();
+    cnode_slot_changed(capability);
+    let _write = slots_changing([slot.get_ptr()]);
     slot.capability = capability.clone();
-    slot.cteMDBNode = mdb_node::new(next as u64, 1u64, 1u64, parent as *const cte_t as u64);
+    slot.cteMDBNode = mdb_node::new(0, 1u64, 1u64, 0);
+    slot.cteMDBNode.set_next_ptr(next);
+    slot.cteMDBNode
+        .set_prev_ptr(parent as *const cte_t as usize);
     if next != 0 {
         let next_ref = convert_to_mut_type_ref::<cte_t>(next);
-        next_ref.cteMDBNode.set_mdbPrev(slot as *const cte_t as u64);
+        next_ref
+            .cteMDBNode
+            .set_prev_ptr(slot as *const cte_t as usize);
     }
-    parent.cteMDBNode.set_mdbNext(slot as *const cte_t as u64);
+    parent
+        .cteMDBNode
+        .set_next_ptr(slot as *const cte_t as usize);
 }
 
 /// 将一个cap插入slot中并删除原节点
 ///
 /// 将一个new_cap插入到dest slot中并作为替代src slot在派生树中的位置
 pub fn cte_move(new_cap: &cap, src_slot: &mut cte_t, dest_slot: &mut cte_t) {
+    cte_move_locked(&NoLock, new_cap, src_slot, dest_slot)
+}
+
+/// 同`cte_move`，`src_slot`、`dest_slot`和`src_slot`的前后节点需要受`guard`保护
+pub fn cte_move_locked<G: MdbGuard>(
+    guard: &G,
+    new_cap: &cap,
+    src_slot: &mut cte_t,
+    dest_slot: &mut cte_t,
+) {
+    let [prev, next] = neighbours(src_slot);
+    assert_locked(
+        guard,
+        &[src_slot.get_ptr(), dest_slot.get_ptr(), prev, next],
+    );
     /* Haskell error: "cteInsert to non-empty destination" */
     assert_eq!(dest_slot.capability.get_tag(), cap_tag::cap_null_cap);
     /* Haskell error: "cteInsert: mdb entry must be empty" */
-    assert!(dest_slot.cteMDBNode.get_mdbNext() == 0 && dest_slot.cteMDBNode.get_mdbPrev() == 0);
+    assert!(dest_slot.cteMDBNode.get_next_ptr() == 0 && dest_slot.cteMDBNode.get_prev_ptr() == 0);
     let mdb = src_slot.cteMDBNode.clone();
+    cnode_slot_changed(&src_slot.capability);
+    cnode_slot_changed(new_cap);
+    let _write = slots_changing([src_slot.get_ptr(), dest_slot.get_ptr()]);
     dest_slot.capability = new_cap.clone();
     src_slot.capability = cap_null_cap::new().unsplay();
     dest_slot.cteMDBNode = mdb.clone();
     src_slot.cteMDBNode = mdb_node::new(0, 0, 0, 0);
 
-    let prev_ptr = mdb.clone().get_mdbPrev() as usize;
+    let prev_ptr = mdb.clone().get_prev_ptr();
     if prev_ptr != 0 {
         let prev_ref = convert_to_mut_type_ref::<cte_t>(prev_ptr);
         prev_ref
             .cteMDBNode
-            .set_mdbNext(dest_slot as *const cte_t as u64);
+            .set_next_ptr(dest_slot as *const cte_t as usize);
     }
-    let next_ptr = mdb.get_mdbNext() as usize;
+    let next_ptr = mdb.get_next_ptr();
     if next_ptr != 0 {
         let next_ref = convert_to_mut_type_ref::<cte_t>(next_ptr);
         next_ref
             .cteMDBNode
-            .set_mdbPrev(dest_slot as *const cte_t as u64);
+            .set_prev_ptr(dest_slot as *const cte_t as usize);
     }
 }
 
 /// 交换两个slot，并将新的cap数据填入
+///
+/// 两个`slot`在派生树中相邻时，互相指向对方的指针也需要交换，否则会形成自环
 pub fn cte_swap(cap1: &cap, slot1: &mut cte_t, cap2: &cap, slot2: &mut cte_t) {
-    let mdb1 = slot1.cteMDBNode.clone();
-    let mdb2 = slot2.cteMDBNode.clone();
-    {
-        let prev_ptr = mdb1.get_mdbPrev() as usize;
-        if prev_ptr != 0 {
-            convert_to_mut_type_ref::<cte_t>(prev_ptr)
-                .cteMDBNode
-                .set_mdbNext(slot2 as *const cte_t as u64);
-        }
-        let next_ptr = mdb1.get_mdbNext() as usize;
-        if next_ptr != 0 {
-            convert_to_mut_type_ref::<cte_t>(next_ptr)
-                .cteMDBNode
-                .set_mdbPrev(slot2 as *const cte_t as u64);
+    cte_swap_locked(&NoLock, cap1, slot1, cap2, slot2)
+}
+
+/// 同`cte_swap`，两个`slot`及其前后节点都需要受`guard`保护
+pub fn cte_swap_locked<G: MdbGuard>(
+    guard: &G,
+    cap1: &cap,
+    slot1: &mut cte_t,
+    cap2: &cap,
+    slot2: &mut cte_t,
+) {
+    let [prev1, next1] = neighbours(slot1);
+    let [prev2, next2] = neighbours(slot2);
+    assert_locked(
+        guard,
+        &[slot1.get_ptr(), slot2.get_ptr(), prev1, next1, prev2, next2],
+    );
+    cnode_slot_changed(cap1);
+    cnode_slot_changed(cap2);
+    let ptr1 = slot1.get_ptr();
+    let ptr2 = slot2.get_ptr();
+    let swap_ptr = |ptr: usize| {
+        if ptr == ptr1 {
+            ptr2
+        } else if ptr == ptr2 {
+            ptr1
+        } else {
+            ptr
         }
+    };
+    let mut mdb1 = slot1.cteMDBNode.clone();
+    let mut mdb2 = slot2.cteMDBNode.clone();
+    relink_neighbours(&mdb1, ptr2);
+    relink_neighbours(&mdb2, ptr1);
+    mdb1.set_prev_ptr(swap_ptr(mdb1.get_prev_ptr()));
+    mdb1.set_next_ptr(swap_ptr(mdb1.get_next_ptr()));
+    mdb2.set_prev_ptr(swap_ptr(mdb2.get_prev_ptr()));
+    mdb2.set_next_ptr(swap_ptr(mdb2.get_next_ptr()));
+
+    let _write = slots_changing([ptr1, ptr2]);
+    slot1.capability = cap2.clone();
+    slot2.capability = cap1.clone();
+    slot1.cteMDBNode = mdb2;
+    slot2.cteMDBNode = mdb1;
+}
+
+/// 将`mdb`前后节点的指针改为指向`new`，跳过`new`本身
+fn relink_neighbours(mdb: &mdb_node, new: usize) {
+    let prev_ptr = mdb.get_prev_ptr();
+    if prev_ptr != 0 && prev_ptr != new {
+        convert_to_mut_type_ref::<cte_t>(prev_ptr)
+            .cteMDBNode
+            .set_next_ptr(new);
+    }
+    let next_ptr = mdb.get_next_ptr();
+    if next_ptr != 0 && next_ptr != new {
+        convert_to_mut_type_ref::<cte_t>(next_ptr)
+            .cteMDBNode
+            .set_prev_ptr(new);
     }
+}
 
-    slot1.capability = cap2.clone();
-    //FIXME::result not right due to compiler
+/// 三个slot的轮换：`pivot`中的cap移动到`dest`，`src`中的cap移动到`pivot`
+///
+/// `pivot_badge`写入最终位于`pivot`的cap（原`src`中的），`dest_badge`写入最终位于`dest`的cap（原`pivot`中的），
+/// 与seL4一致通过`update_data(true, ..)`写入，更新后为`null_cap`时不做任何修改并返回`EXCEPTION_SYSCALL_ERROR`。
+///
+/// `src`与`dest`可以是同一个`slot`，此时等价于`src`和`pivot`交换，所以两者以指针传入。
+/// `dest`与`src`不同时必须为空。`mdbRevocable`和`mdbFirstBadged`随cap一起移动。
+/// `pivot`与`src`或`dest`相同时不做任何修改并返回`EXCEPTION_SYSCALL_ERROR`。
+///
+/// # Safety
+///
+/// `src`和`dest`必须指向有效的`cte_t`，并且在调用期间除了`pivot`之外没有其他指向这两个`slot`的引用。
+pub unsafe fn cte_rotate(
+    pivot: &mut cte_t,
+    src: *mut cte_t,
+    dest: *mut cte_t,
+    pivot_badge: u64,
+    dest_badge: u64,
+) -> exception_t {
+    cte_rotate_locked(&NoLock, pivot, src, dest, pivot_badge, dest_badge)
+}
 
-    slot2.capability = cap1.clone();
-    slot1.cteMDBNode = mdb2.clone();
-    slot2.cteMDBNode = mdb1.clone();
+/// 同`cte_rotate`，三个`slot`及`pivot`、`src`的前后节点都需要受`guard`保护
+///
+/// # Safety
+///
+/// 同`cte_rotate`
+pub unsafe fn cte_rotate_locked<G: MdbGuard>(
+    guard: &G,
+    pivot: &mut cte_t,
+    src: *mut cte_t,
+    dest: *mut cte_t,
+    pivot_badge: u64,
+    dest_badge: u64,
+) -> exception_t {
+    let pivot_ptr = pivot as *mut cte_t;
+    if src == pivot_ptr || dest == pivot_ptr {
+        return exception_t::EXCEPTION_SYSCALL_ERROR;
+    }
+    let src_slot = &mut *src;
+    let new_src_cap = src_slot.capability.update_data(true, pivot_badge);
+    let new_pivot_cap = pivot.capability.update_data(true, dest_badge);
+    if new_src_cap.get_tag() == cap_tag::cap_null_cap
+        || new_pivot_cap.get_tag() == cap_tag::cap_null_cap
     {
-        let prev_ptr = mdb2.get_mdbPrev() as usize;
-        if prev_ptr != 0 {
-            convert_to_mut_type_ref::<cte_t>(prev_ptr)
-                .cteMDBNode
-                .set_mdbNext(slot1 as *const cte_t as u64);
-        }
-        let next_ptr = mdb2.get_mdbNext() as usize;
-        if next_ptr != 0 {
-            convert_to_mut_type_ref::<cte_t>(next_ptr)
-                .cteMDBNode
-                .set_mdbPrev(slot1 as *const cte_t as u64);
-        }
+        return exception_t::EXCEPTION_SYSCALL_ERROR;
+    }
+    if src == dest {
+        cte_swap_locked(guard, &new_src_cap, src_slot, &new_pivot_cap, pivot);
+    } else {
+        let dest_slot = &mut *dest;
+        cte_move_locked(guard, &new_pivot_cap, pivot, dest_slot);
+        cte_move_locked(guard, &new_src_cap, src_slot, pivot);
+    }
+    exception_t::EXCEPTION_NONE
+}
+
+/// 存放或将要存放`capability`的`slot`发生变化，如果是`CNode cap`则翻译缓存需要失效
+#[inline]
+fn cnode_slot_changed(capability: &cap) {
+    #[cfg(feature = "lookup_cache")]
+    if capability.get_tag() == cap_tag::cap_cnode_cap {
+        crate::lookup_cache::invalidate();
+    }
+    #[cfg(not(feature = "lookup_cache"))]
+    let _ = capability;
+}
+
+/// 即将修改`slots`中的`cap`，开启`seqlock_lookup`时，返回值drop之前这些`slot`上的无锁查找都会重试
+#[inline]
+pub(crate) fn slots_changing<const N: usize>(slots: [usize; N]) -> impl Sized {
+    #[cfg(feature = "seqlock_lookup")]
+    {
+        crate::seqlock::SlotWrite::begin(slots)
+    }
+    #[cfg(not(feature = "seqlock_lookup"))]
+    {
+        let _ = slots;
     }
 }
 
@@ -511,12 +793,46 @@ fn set_untyped_cap_as_full(srcCap: &cap, newCap: &cap, srcSlot: &mut cte_t) {
 ///
 /// 从给定的cnode、cap index、和depth中找到对应cap的slot，成功则返回slot指针，失败返回找到的最深的cnode
 ///
+/// 失败时`lookupFault`记录了具体原因，内核可以直接据此构造`seL4_LookupFault`
+///
 /// Parse cap_ptr ,get a capbility from cnode.
-#[allow(unreachable_code)]
+///
+/// 开启`lookup_cache` feature时会先查询每核的翻译缓存
+#[inline]
 pub fn resolve_address_bits(
+    node_cap: &cap,
+    cap_ptr: usize,
+    n_bits: usize,
+) -> resolveAddressBits_ret_t {
+    #[cfg(feature = "lookup_cache")]
+    {
+        crate::lookup_cache::resolve(node_cap, cap_ptr, n_bits)
+    }
+    #[cfg(not(feature = "lookup_cache"))]
+    {
+        resolve_address_bits_uncached(node_cap, cap_ptr, n_bits)
+    }
+}
+
+/// 不经过缓存，逐级遍历`CNode`
+#[inline]
+pub(crate) fn resolve_address_bits_uncached(
+    node_cap: &cap,
+    cap_ptr: usize,
+    n_bits: usize,
+) -> resolveAddressBits_ret_t {
+    resolve_address_bits_walk(node_cap, cap_ptr, n_bits, |slot| unsafe {
+        (*slot).capability.clone()
+    })
+}
+
+/// 逐级遍历`CNode`，中间各级`slot`中的`cap`通过`read_slot`读取
+#[allow(unreachable_code)]
+pub(crate) fn resolve_address_bits_walk(
     node_cap: &cap,
     cap_ptr: usize,
     _n_bits: usize,
+    mut read_slot: impl FnMut(*mut cte_t) -> cap,
 ) -> resolveAddressBits_ret_t {
     let mut ret = resolveAddressBits_ret_t::default();
     let mut n_bits = _n_bits;
@@ -525,6 +841,7 @@ pub fn resolve_address_bits(
 
     if unlikely(nodeCap.clone().get_tag() != cap_tag::cap_cnode_cap) {
         ret.status = exception_t::EXCEPTION_LOOKUP_FAULT;
+        ret.lookupFault = LookupFault::InvalidRoot;
         return ret;
     }
 
@@ -539,10 +856,20 @@ pub fn resolve_address_bits(
             (cap_ptr >> ((n_bits - guardBits) & mask_bits!(WORD_RADIX))) & mask_bits!(guardBits);
         if unlikely(guardBits > n_bits || guard != capGuard) {
             ret.status = exception_t::EXCEPTION_LOOKUP_FAULT;
+            ret.lookupFault = LookupFault::GuardMismatch {
+                guard_found: guard,
+                guard_expected: capGuard,
+                bits_left: n_bits,
+                guard_size: guardBits,
+            };
             return ret;
         }
         if unlikely(levelBits > n_bits) {
             ret.status = exception_t::EXCEPTION_LOOKUP_FAULT;
+            ret.lookupFault = LookupFault::DepthMismatch {
+                bits_found: levelBits,
+                bits_left: n_bits,
+            };
             return ret;
         }
         let offset = (cap_ptr >> (n_bits - levelBits)) & mask_bits!(radixBits);
@@ -554,7 +881,7 @@ pub fn resolve_address_bits(
             return ret;
         }
         n_bits -= levelBits;
-        nodeCap = unsafe { (*slot).capability.clone() };
+        nodeCap = read_slot(slot);
         if unlikely(nodeCap.clone().get_tag() != cap_tag::cap_cnode_cap) {
             ret.slot = slot;
             ret.bitsRemaining = n_bits;
@@ -0,0 +1,262 @@
+//! 以CapDL格式导出一个`CSpace`
+//!
+//! 输出分为`objects`和`caps`两部分，对象以`类型_地址`命名，例如`ep_0x80010000`。
+//! 同一个对象只在第一次出现时声明，同一个`CNode`的`slot`也只输出一次。
+//! 为了不依赖堆内存，去重通过重新遍历完成，复杂度为`slot`数的平方，只适合调试时使用。
+use super::for_each_slot;
+use crate::capability::cap_arch_func;
+use crate::cte::cte_t;
+use core::fmt::{Result, Write};
+use sel4_common::structures_gen::{cap, cap_cnode_cap, cap_tag};
+use sel4_common::utils::{convert_to_type_ref, pageBitsForSize};
+
+/// 将`root`为根的`CSpace`以CapDL格式写入`out`
+pub fn dump_cspace(root: &cap_cnode_cap, out: &mut dyn Write) -> Result {
+    #[cfg(target_arch = "riscv64")]
+    writeln!(out, "arch riscv64")?;
+    #[cfg(target_arch = "riscv32")]
+    writeln!(out, "arch riscv32")?;
+    #[cfg(target_arch = "aarch64")]
+    writeln!(out, "arch aarch64")?;
+    #[cfg(target_arch = "x86_64")]
+    writeln!(out, "arch x86_64")?;
+    #[cfg(target_arch = "loongarch64")]
+    writeln!(out, "arch loongarch64")?;
+    writeln!(out)?;
+
+    let root_id = ("cnode", root.get_capCNodePtr() as usize);
+    let mut result = Ok(());
+    let mut seq = 0;
+    writeln!(out, "objects {{")?;
+    writeln!(
+        out,
+        "    cnode_{:#x} = cnode ({} bits)",
+        root_id.1,
+        root.get_capCNodeRadix()
+    )?;
+    for_each_slot(root, &mut |_, _, slot| {
+        seq += 1;
+        let Some(id) = object_id(&slot.capability) else {
+            return true;
+        };
+        if id == root_id
+            || seen_before(root, seq, &mut |_, _, other| {
+                object_id(&other.capability) == Some(id)
+            })
+        {
+            return true;
+        }
+        result = write_object(out, &slot.capability);
+        result.is_ok()
+    });
+    result?;
+    writeln!(out, "}}")?;
+
+    seq = 0;
+    writeln!(out, "caps {{")?;
+    for_each_slot(root, &mut |cnode, index, _| {
+        seq += 1;
+        if index != 0 {
+            return true;
+        }
+        let ptr = cnode.get_capCNodePtr();
+        if seen_before(root, seq, &mut |other, i, _| {
+            i == 0 && other.get_capCNodePtr() == ptr
+        }) {
+            return true;
+        }
+        result = write_cnode_caps(out, cnode);
+        result.is_ok()
+    });
+    result?;
+    writeln!(out, "}}")
+}
+
+/// 对象的类型名和地址，不指向对象的`cap`返回`None`
+fn object_id(capability: &cap) -> Option<(&'static str, usize)> {
+    let kind = match capability.get_tag() {
+        cap_tag::cap_untyped_cap => "ut",
+        cap_tag::cap_endpoint_cap => "ep",
+        cap_tag::cap_notification_cap => "ntfn",
+        cap_tag::cap_cnode_cap => "cnode",
+        cap_tag::cap_thread_cap => "tcb",
+        cap_tag::cap_irq_handler_cap => {
+            return Some((
+                "irq",
+                cap::cap_irq_handler_cap(capability).get_capIRQ() as usize,
+            ))
+        }
+        cap_tag::cap_frame_cap => "frame",
+        cap_tag::cap_page_table_cap => "pt",
+        #[cfg(target_arch = "aarch64")]
+        cap_tag::cap_vspace_cap => "vspace",
+        #[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+        cap_tag::cap_vcpu_cap => "vcpu",
+        #[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+        cap_tag::cap_page_directory_cap => "pd",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_pdpt_cap => "pdpt",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_pml4_cap => "pml4",
+        cap_tag::cap_asid_pool_cap => "asid_pool",
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_reply_cap => "reply",
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_sched_context_cap => "sc",
+        _ => return None,
+    };
+    Some((kind, capability.get_cap_ptr()))
+}
+
+/// 遍历顺序中第`seq`次访问之前，是否有`slot`满足`pred`
+///
+/// 同一个`CNode`可能从不同路径被访问多次，所以用访问序号而不是`slot`地址来判断先后
+fn seen_before(
+    root: &cap_cnode_cap,
+    seq: usize,
+    pred: &mut dyn FnMut(&cap_cnode_cap, usize, &cte_t) -> bool,
+) -> bool {
+    let mut count = 0;
+    let mut found = false;
+    for_each_slot(root, &mut |cnode, index, slot| {
+        count += 1;
+        if count >= seq {
+            return false;
+        }
+        found = pred(cnode, index, slot);
+        !found
+    });
+    found
+}
+
+fn write_cnode_caps(out: &mut dyn Write, cnode: &cap_cnode_cap) -> Result {
+    let ptr = cnode.get_capCNodePtr() as usize;
+    writeln!(out, "    cnode_{:#x} {{", ptr)?;
+    for index in 0..(1usize << cnode.get_capCNodeRadix()) {
+        let slot = convert_to_type_ref::<cte_t>(ptr + index * core::mem::size_of::<cte_t>());
+        if slot.capability.get_tag() == cap_tag::cap_null_cap {
+            continue;
+        }
+        write!(out, "        {:#x}: ", index)?;
+        write_cap(out, &slot.capability)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "    }}")
+}
+
+fn write_object(out: &mut dyn Write, capability: &cap) -> Result {
+    let (kind, ptr) = object_id(capability).unwrap();
+    write!(out, "    {}_{:#x} = ", kind, ptr)?;
+    match capability.get_tag() {
+        cap_tag::cap_untyped_cap => {
+            let ut = cap::cap_untyped_cap(capability);
+            write!(out, "ut ({} bits, paddr: {:#x}", ut.get_capBlockSize(), ptr)?;
+            if ut.get_capIsDevice() != 0 {
+                write!(out, ", device")?;
+            }
+            writeln!(out, ")")
+        }
+        cap_tag::cap_endpoint_cap => writeln!(out, "ep"),
+        cap_tag::cap_notification_cap => writeln!(out, "notification"),
+        cap_tag::cap_cnode_cap => writeln!(
+            out,
+            "cnode ({} bits)",
+            cap::cap_cnode_cap(capability).get_capCNodeRadix()
+        ),
+        cap_tag::cap_thread_cap => writeln!(out, "tcb"),
+        cap_tag::cap_irq_handler_cap => writeln!(out, "irq"),
+        cap_tag::cap_frame_cap => writeln!(
+            out,
+            "frame ({} bits, paddr: {:#x})",
+            pageBitsForSize(cap::cap_frame_cap(capability).get_capFSize() as usize),
+            ptr
+        ),
+        cap_tag::cap_page_table_cap => writeln!(out, "pt"),
+        cap_tag::cap_asid_pool_cap => writeln!(out, "asid_pool"),
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_reply_cap => writeln!(out, "rtreply"),
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_sched_context_cap => {
+            writeln!(
+                out,
+                "sc ({} bits)",
+                cap::cap_sched_context_cap(capability).get_capSCSizeBits()
+            )
+        }
+        _ => writeln!(out, "{}", kind),
+    }
+}
+
+fn write_cap(out: &mut dyn Write, capability: &cap) -> Result {
+    let Some((kind, ptr)) = object_id(capability) else {
+        let name = match capability.get_tag() {
+            cap_tag::cap_irq_control_cap => "irq_control",
+            cap_tag::cap_asid_control_cap => "asid_control",
+            cap_tag::cap_domain_cap => "domain",
+            #[cfg(target_arch = "x86_64")]
+            cap_tag::cap_io_port_control_cap => "io_port_control",
+            #[cfg(feature = "kernel_mcs")]
+            cap_tag::cap_sched_control_cap => "sched_control",
+            _ => "-- unknown cap",
+        };
+        return write!(out, "{}", name);
+    };
+    write!(out, "{}_{:#x}", kind, ptr)?;
+    match capability.get_tag() {
+        cap_tag::cap_endpoint_cap => {
+            let ep = cap::cap_endpoint_cap(capability);
+            write!(out, " (")?;
+            write_rights(
+                out,
+                ep.get_capCanReceive() != 0,
+                ep.get_capCanSend() != 0,
+                ep.get_capCanGrant() != 0,
+                ep.get_capCanGrantReply() != 0,
+            )?;
+            if ep.get_capEPBadge() != 0 {
+                write!(out, ", badge: {}", ep.get_capEPBadge())?;
+            }
+            write!(out, ")")
+        }
+        cap_tag::cap_notification_cap => {
+            let ntfn = cap::cap_notification_cap(capability);
+            write!(out, " (")?;
+            write_rights(
+                out,
+                ntfn.get_capNtfnCanReceive() != 0,
+                ntfn.get_capNtfnCanSend() != 0,
+                false,
+                false,
+            )?;
+            if ntfn.get_capNtfnBadge() != 0 {
+                write!(out, ", badge: {}", ntfn.get_capNtfnBadge())?;
+            }
+            write!(out, ")")
+        }
+        cap_tag::cap_cnode_cap => {
+            let cnode = cap::cap_cnode_cap(capability);
+            write!(
+                out,
+                " (guard: {}, guard_size: {})",
+                cnode.get_capCNodeGuard(),
+                cnode.get_capCNodeGuardSize()
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+fn write_rights(
+    out: &mut dyn Write,
+    read: bool,
+    write: bool,
+    grant: bool,
+    grant_reply: bool,
+) -> Result {
+    for (set, c) in [(read, 'R'), (write, 'W'), (grant, 'G'), (grant_reply, 'P')] {
+        if set {
+            out.write_char(c)?;
+        }
+    }
+    Ok(())
+}
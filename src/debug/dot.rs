@@ -0,0 +1,54 @@
+//! 以Graphviz DOT格式导出能力派生树
+//!
+//! 输出可以直接从串口复制出来，交给`dot -Tsvg`渲染。
+use super::cap_tag_name;
+use crate::capability::cap_arch_func;
+use crate::cte::{cte_t, mdb_ptr_func};
+use crate::mdb::badge_of;
+use core::fmt::{Result, Write};
+use sel4_common::utils::convert_to_type_ref;
+
+/// 导出以`root`为根的派生子树，包括`root`本身
+///
+/// 子树在`mdb_node`链表中是紧跟在`root`之后的一段，每个节点的父节点是它之前最近的、
+/// `is_mdb_parent_of`成立的节点，这样不需要递归也不需要额外的栈空间。
+pub fn export_dot(root: &cte_t, out: &mut dyn Write) -> Result {
+    writeln!(out, "digraph mdb {{")?;
+    writeln!(out, "    node [shape=box];")?;
+    write_node(out, root)?;
+    for slot in root.descendants() {
+        write_node(out, slot)?;
+        let mut parent = slot.cteMDBNode.get_prev_ptr();
+        while parent != root.get_ptr() {
+            let candidate = convert_to_type_ref::<cte_t>(parent);
+            if candidate.is_mdb_parent_of(slot) {
+                break;
+            }
+            parent = candidate.cteMDBNode.get_prev_ptr();
+        }
+        writeln!(out, "    n{:#x} -> n{:#x};", parent, slot.get_ptr())?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_node(out: &mut dyn Write, slot: &cte_t) -> Result {
+    let capability = &slot.capability;
+    write!(
+        out,
+        "    n{:#x} [label=\"{}\\nslot: {:#x}\\nptr: {:#x}",
+        slot.get_ptr(),
+        cap_tag_name(capability.get_tag()),
+        slot.get_ptr(),
+        capability.get_cap_ptr()
+    )?;
+    let badge = badge_of(capability);
+    if badge != 0 {
+        write!(out, "\\nbadge: {}", badge)?;
+    }
+    writeln!(
+        out,
+        "\\nrevocable: {}, firstBadged: {}\"];",
+        slot.cteMDBNode.get_mdbRevocable(),
+        slot.cteMDBNode.get_mdbFirstBadged()
+    )
+}
@@ -0,0 +1,104 @@
+//! 调试用的`CSpace`导出工具，输出均写入`core::fmt::Write`，可以直接打印到串口
+pub mod capdl;
+pub mod dot;
+
+use crate::cte::cte_t;
+use sel4_common::sel4_config::WORD_BITS;
+use sel4_common::structures_gen::{cap, cap_cnode_cap, cap_tag};
+use sel4_common::utils::convert_to_type_ref;
+
+/// 按`resolve_address_bits`的方式遍历`root`下的所有`slot`
+///
+/// 嵌套的`CNode`只在guard和radix累计不超过`WORD_BITS`时继续展开，同一条路径上重复出现的`CNode`会被跳过。
+/// `f`的参数为`slot`所在的`CNode`、`slot`下标和`slot`本身，返回`false`时停止遍历。
+pub(crate) fn for_each_slot(
+    root: &cap_cnode_cap,
+    f: &mut dyn FnMut(&cap_cnode_cap, usize, &cte_t) -> bool,
+) -> bool {
+    let mut path = [0usize; WORD_BITS];
+    walk_cnode(root, 0, &mut path, 0, f)
+}
+
+fn walk_cnode(
+    cnode: &cap_cnode_cap,
+    bits: usize,
+    path: &mut [usize; WORD_BITS],
+    depth: usize,
+    f: &mut dyn FnMut(&cap_cnode_cap, usize, &cte_t) -> bool,
+) -> bool {
+    let radix = cnode.get_capCNodeRadix() as usize;
+    let level = radix + cnode.get_capCNodeGuardSize() as usize;
+    let ptr = cnode.get_capCNodePtr() as usize;
+    if level == 0 || bits + level > WORD_BITS || path[..depth].contains(&ptr) {
+        return true;
+    }
+    path[depth] = ptr;
+    for index in 0..(1usize << radix) {
+        let slot = convert_to_type_ref::<cte_t>(ptr + index * core::mem::size_of::<cte_t>());
+        if !f(cnode, index, slot) {
+            return false;
+        }
+        if slot.capability.get_tag() == cap_tag::cap_cnode_cap
+            && !walk_cnode(
+                cap::cap_cnode_cap(&slot.capability),
+                bits + level,
+                path,
+                depth + 1,
+                f,
+            )
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// `cap_tag`对应的名字
+pub(crate) fn cap_tag_name(tag: u64) -> &'static str {
+    match tag {
+        cap_tag::cap_null_cap => "cap_null_cap",
+        cap_tag::cap_untyped_cap => "cap_untyped_cap",
+        cap_tag::cap_endpoint_cap => "cap_endpoint_cap",
+        cap_tag::cap_notification_cap => "cap_notification_cap",
+        cap_tag::cap_reply_cap => "cap_reply_cap",
+        cap_tag::cap_cnode_cap => "cap_cnode_cap",
+        cap_tag::cap_thread_cap => "cap_thread_cap",
+        cap_tag::cap_irq_control_cap => "cap_irq_control_cap",
+        cap_tag::cap_irq_handler_cap => "cap_irq_handler_cap",
+        cap_tag::cap_zombie_cap => "cap_zombie_cap",
+        cap_tag::cap_domain_cap => "cap_domain_cap",
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_sched_context_cap => "cap_sched_context_cap",
+        #[cfg(feature = "kernel_mcs")]
+        cap_tag::cap_sched_control_cap => "cap_sched_control_cap",
+        cap_tag::cap_frame_cap => "cap_frame_cap",
+        cap_tag::cap_page_table_cap => "cap_page_table_cap",
+        #[cfg(target_arch = "aarch64")]
+        cap_tag::cap_vspace_cap => "cap_vspace_cap",
+        #[cfg(all(target_arch = "aarch64", feature = "hypervisor"))]
+        cap_tag::cap_vcpu_cap => "cap_vcpu_cap",
+        #[cfg(all(target_arch = "aarch64", feature = "arm_smmu"))]
+        cap_tag::cap_sid_control_cap => "cap_sid_control_cap",
+        #[cfg(all(target_arch = "aarch64", feature = "arm_smmu"))]
+        cap_tag::cap_sid_cap => "cap_sid_cap",
+        #[cfg(all(target_arch = "aarch64", feature = "arm_smmu"))]
+        cap_tag::cap_cb_control_cap => "cap_cb_control_cap",
+        #[cfg(all(target_arch = "aarch64", feature = "arm_smmu"))]
+        cap_tag::cap_cb_cap => "cap_cb_cap",
+        #[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+        cap_tag::cap_page_directory_cap => "cap_page_directory_cap",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_pdpt_cap => "cap_pdpt_cap",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_pml4_cap => "cap_pml4_cap",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_io_port_control_cap => "cap_io_port_control_cap",
+        #[cfg(target_arch = "x86_64")]
+        cap_tag::cap_io_port_cap => "cap_io_port_cap",
+        cap_tag::cap_asid_control_cap => "cap_asid_control_cap",
+        cap_tag::cap_asid_pool_cap => "cap_asid_pool_cap",
+        #[cfg(feature = "enable_smc")]
+        cap_tag::cap_smc_cap => "cap_smc_cap",
+        _ => "unknown",
+    }
+}
@@ -7,4 +7,71 @@ extern "C" {
     pub fn post_cap_deletion(capability: &cap);
 
     pub fn preemption_point() -> exception_t;
+
+    pub fn cancel_badged_sends(ep_ptr: usize, badge: u64);
+
+    /// 当前核的编号，用于索引每核的翻译缓存
+    #[cfg(feature = "lookup_cache")]
+    pub fn get_current_cpu_index() -> usize;
+}
+
+/// 删除、撤销`cap`以及`CNode`操作过程中需要由内核提供的操作
+///
+/// `cte_t`中带`_with`后缀的方法都以该trait为泛型参数，测试或其他内核可以提供自己的实现。
+/// 后续需要新的内核回调时直接在这里添加即可。
+pub trait CSpaceHooks {
+    /// 对应seL4中的`finaliseCap`，返回剩余的`cap`和需要清理的信息
+    fn finalise_cap(capability: &cap, _final: bool, exposed: bool) -> FinaliseCapRet;
+
+    /// `cap`从`slot`中移除之后的清理工作
+    fn post_cap_deletion(capability: &cap);
+
+    /// 检查是否有待处理的中断，用于长时间操作的抢占
+    fn preemption_point() -> exception_t;
+
+    /// 取消`endpoint`上所有带有`badge`的发送者，用于`seL4_CNode_CancelBadgedSends`
+    ///
+    /// 默认转发到内核导出的`cancel_badged_sends`，已有的实现不需要修改
+    #[inline]
+    fn cancel_badged_sends(ep_ptr: usize, badge: u64) {
+        unsafe { cancel_badged_sends(ep_ptr, badge) }
+    }
+}
+
+/// 默认实现，转发到rel4内核导出的外部符号
+pub struct KernelHooks;
+
+impl CSpaceHooks for KernelHooks {
+    #[inline]
+    fn finalise_cap(capability: &cap, _final: bool, exposed: bool) -> FinaliseCapRet {
+        unsafe { finalise_cap(capability, _final, exposed) }
+    }
+
+    #[inline]
+    fn post_cap_deletion(capability: &cap) {
+        unsafe { post_cap_deletion(capability) }
+    }
+
+    #[inline]
+    fn preemption_point() -> exception_t {
+        unsafe { preemption_point() }
+    }
+}
+
+/// 从`untyped`中创建对象时需要由内核提供的操作
+///
+/// 没有对应的外部符号和默认实现，使用`untyped`模块的内核需要自己实现该trait，
+/// 不使用的内核也不会因此多出链接依赖。
+pub trait UntypedHooks {
+    /// TCB和架构相关对象的大小（以2为底的对数），不支持的类型返回`None`
+    fn object_size(obj_type: usize, user_size: usize) -> Option<usize>;
+
+    /// 是否为页帧类型，设备内存只能用于创建页帧和`untyped`
+    fn is_frame_type(obj_type: usize) -> bool;
+
+    /// 初始化`region_base`处的对象并返回指向它的`cap`
+    fn create_object(obj_type: usize, region_base: usize, user_size: usize, device: bool) -> cap;
+
+    /// 将`base`开始的`2^size_bits`字节清零，用于重置`untyped`
+    fn clear_memory(base: usize, size_bits: usize);
 }
@@ -1,4 +1,31 @@
-pub use super::capability::same_object_as;
+pub use super::arch::{set_unknown_arch_cap_hook, KernelLevel, KernelWindow, UnknownArchCapHook};
+pub use super::capability::typed::{
+    CNode, Cap, CapType, Endpoint, Notification, Thread, Untyped, WrongCapType,
+};
+pub use super::capability::{mask_cap_rights, same_object_as};
 
-pub use super::cte::{cte_insert, cte_move, cte_swap, cte_t, insert_new_cap, resolve_address_bits};
-pub use super::structures::FinaliseCapRet;
+pub use super::cte::{
+    cte_insert, cte_insert_locked, cte_move, cte_move_locked, cte_rotate, cte_rotate_locked,
+    cte_swap, cte_swap_locked, cte_t, insert_new_cap, insert_new_cap_locked, resolve_address_bits,
+};
+pub use super::debug::capdl::dump_cspace;
+pub use super::debug::dot::export_dot;
+pub use super::deps::{CSpaceHooks, KernelHooks, UntypedHooks};
+#[cfg(not(feature = "kernel_mcs"))]
+pub use super::invocation::cnode_save_caller;
+pub use super::invocation::{
+    cnode_cancel_badged_sends, cnode_cancel_badged_sends_with, cnode_copy, cnode_delete,
+    cnode_delete_with, cnode_mint, cnode_move, cnode_mutate, cnode_revoke, cnode_revoke_with,
+    cnode_rotate, CNodeError, SlotAddr,
+};
+pub use super::lock::{BigLockGuard, MdbGuard, NoLock, RegionGuard};
+pub use super::mdb::{
+    validate_mdb, validate_mdb_range, MdbChildren, MdbDescendants, MdbReport, MdbSameObject,
+    MdbViolation,
+};
+#[cfg(feature = "seqlock_lookup")]
+pub use super::seqlock::{resolve_address_bits_lockfree, LookupSnapshot};
+pub use super::structures::{
+    resolveAddressBits_ret_t, DeleteProgress, FinaliseCapRet, LookupFault, RevokeProgress,
+};
+pub use super::untyped::untyped_retype_with;
@@ -0,0 +1,327 @@
+//! `CNode`的各个方法，对应seL4中`decodeCNodeInvocation`之后的部分
+//!
+//! 内核负责从消息寄存器和`extraCaps`中取出参数，这里完成`slot`寻址、空`slot`检查、
+//! 派生、权限削减、`badge`更新和插入。失败时返回的`CNodeError`可以直接转换为
+//! `seL4_Error`编号和`exception_t`：
+//!
+//! ```ignore
+//! match cnode_mint(dest, src, rights, badge) {
+//!     Ok(()) => exception_t::EXCEPTION_NONE,
+//!     Err(e) => {
+//!         current_syscall_error._type = e.code();
+//!         e.exception()
+//!     }
+//! }
+//! ```
+use crate::capability::{cap_func, mask_cap_rights};
+use crate::cte::{cte_insert, cte_move, cte_rotate, cte_t, resolve_address_bits};
+use crate::deps::{CSpaceHooks, KernelHooks};
+use crate::lock::NoLock;
+use crate::structures::LookupFault;
+use sel4_common::sel4_config::{
+    seL4_DeleteFirst, seL4_FailedLookup, seL4_IllegalOperation, seL4_InvalidArgument, seL4_NoError,
+    seL4_NotEnoughMemory, seL4_RangeError, seL4_RevokeFirst, WORD_BITS,
+};
+use sel4_common::shared_types_bf_gen::seL4_CapRights;
+use sel4_common::structures::exception_t;
+use sel4_common::structures_gen::{cap, cap_tag};
+use sel4_common::utils::{convert_to_mut_type_ref, convert_to_type_ref};
+
+/// `CNode`操作中的一个`slot`地址：根`CNode cap`、`cap_ptr`和解析深度
+#[derive(Clone, Copy)]
+pub struct SlotAddr<'a> {
+    pub root: &'a cap,
+    pub index: usize,
+    pub depth: usize,
+}
+
+impl<'a> SlotAddr<'a> {
+    #[inline]
+    pub fn new(root: &'a cap, index: usize, depth: usize) -> Self {
+        SlotAddr { root, index, depth }
+    }
+}
+
+/// `CNode`和`untyped`操作失败的原因，除`Interrupted`外都对应一个`seL4_Error`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CNodeError {
+    /// `seL4_InvalidArgument`：第`index`个参数不合法
+    InvalidArgument { index: usize },
+    /// `seL4_IllegalOperation`
+    IllegalOperation,
+    /// `seL4_RangeError`：解析深度不在`[min, max]`内
+    RangeError { min: usize, max: usize },
+    /// `seL4_FailedLookup`：`source`表示失败的是源`slot`
+    FailedLookup { source: bool, fault: LookupFault },
+    /// `seL4_DeleteFirst`：目标`slot`不为空
+    DeleteFirst,
+    /// `seL4_RevokeFirst`：`untyped_cap`还有子节点
+    RevokeFirst,
+    /// `seL4_NotEnoughMemory`：`untyped`剩余空间只能容纳`available`个对象
+    NotEnoughMemory { available: usize },
+    /// 删除或撤销没有完成，例如被抢占，内核需要原样返回该`exception_t`
+    Interrupted(exception_t),
+}
+
+impl CNodeError {
+    /// 对应的`seL4_Error`编号，`Interrupted`返回`seL4_NoError`
+    pub fn code(&self) -> usize {
+        match self {
+            CNodeError::InvalidArgument { .. } => seL4_InvalidArgument,
+            CNodeError::IllegalOperation => seL4_IllegalOperation,
+            CNodeError::RangeError { .. } => seL4_RangeError,
+            CNodeError::FailedLookup { .. } => seL4_FailedLookup,
+            CNodeError::DeleteFirst => seL4_DeleteFirst,
+            CNodeError::RevokeFirst => seL4_RevokeFirst,
+            CNodeError::NotEnoughMemory { .. } => seL4_NotEnoughMemory,
+            CNodeError::Interrupted(_) => seL4_NoError,
+        }
+    }
+
+    /// 系统调用应返回的`exception_t`
+    pub fn exception(&self) -> exception_t {
+        match self {
+            CNodeError::Interrupted(status) => *status,
+            _ => exception_t::EXCEPTION_SYSCALL_ERROR,
+        }
+    }
+}
+
+/// `seL4_CNode_Copy`：派生一个削减了权限的`cap`放入`dest`
+pub fn cnode_copy(dest: SlotAddr, src: SlotAddr, rights: seL4_CapRights) -> Result<(), CNodeError> {
+    let dest_slot = lookup_empty_slot(&dest)?;
+    let src_slot = lookup_source_cap(&src)?;
+    let new_cap = derive(src_slot, &mask_cap_rights(rights, &src_slot.capability))?;
+    cte_insert(&new_cap, src_slot, dest_slot);
+    Ok(())
+}
+
+/// `seL4_CNode_Mint`：同`cnode_copy`，并设置`badge`或`CNode`的guard
+pub fn cnode_mint(
+    dest: SlotAddr,
+    src: SlotAddr,
+    rights: seL4_CapRights,
+    badge: u64,
+) -> Result<(), CNodeError> {
+    let dest_slot = lookup_empty_slot(&dest)?;
+    let src_slot = lookup_source_cap(&src)?;
+    let masked = mask_cap_rights(rights, &src_slot.capability);
+    let new_cap = derive(src_slot, &masked.update_data(false, badge))?;
+    cte_insert(&new_cap, src_slot, dest_slot);
+    Ok(())
+}
+
+/// `seL4_CNode_Move`：将`src`中的`cap`原样移动到`dest`
+pub fn cnode_move(dest: SlotAddr, src: SlotAddr) -> Result<(), CNodeError> {
+    let dest_slot = lookup_empty_slot(&dest)?;
+    let src_slot = lookup_source_cap(&src)?;
+    let new_cap = src_slot.capability.clone();
+    cte_move(&new_cap, src_slot, dest_slot);
+    Ok(())
+}
+
+/// `seL4_CNode_Mutate`：移动的同时更新`cap`的数据，不能修改已有的`badge`
+pub fn cnode_mutate(dest: SlotAddr, src: SlotAddr, data: u64) -> Result<(), CNodeError> {
+    let dest_slot = lookup_empty_slot(&dest)?;
+    let src_slot = lookup_source_cap(&src)?;
+    let new_cap = src_slot.capability.update_data(true, data);
+    if new_cap.get_tag() == cap_tag::cap_null_cap {
+        return Err(CNodeError::IllegalOperation);
+    }
+    cte_move(&new_cap, src_slot, dest_slot);
+    Ok(())
+}
+
+/// `seL4_CNode_Rotate`：`pivot`移动到`dest`，`src`移动到`pivot`，`src`与`dest`可以相同
+///
+/// `dest_data`写入最终位于`dest`的`cap`，`pivot_data`写入最终位于`pivot`的`cap`
+pub fn cnode_rotate(
+    dest: SlotAddr,
+    pivot: SlotAddr,
+    src: SlotAddr,
+    dest_data: u64,
+    pivot_data: u64,
+) -> Result<(), CNodeError> {
+    let dest_ptr = lookup_slot(&dest, false)?.get_ptr();
+    // 与seL4的`lookupPivotSlot`一致，`pivot`按源`slot`查找
+    let pivot_slot = lookup_slot(&pivot, true)?;
+    let src_slot = lookup_slot(&src, true)?;
+    let pivot_ptr = pivot_slot.get_ptr();
+    let src_ptr = src_slot.get_ptr();
+    if pivot_ptr == src_ptr || pivot_ptr == dest_ptr {
+        return Err(CNodeError::IllegalOperation);
+    }
+    if src_ptr != dest_ptr
+        && convert_to_type_ref::<cte_t>(dest_ptr).capability.get_tag() != cap_tag::cap_null_cap
+    {
+        return Err(CNodeError::DeleteFirst);
+    }
+    if src_slot.capability.get_tag() == cap_tag::cap_null_cap {
+        return Err(missing_capability(true, src.depth));
+    }
+    if pivot_slot.capability.get_tag() == cap_tag::cap_null_cap {
+        return Err(missing_capability(false, pivot.depth));
+    }
+    // 上面已经排除了`pivot`与`src`、`dest`相同的情况，`src_slot`之后不再使用
+    match unsafe {
+        cte_rotate(
+            pivot_slot,
+            src_ptr as *mut cte_t,
+            dest_ptr as *mut cte_t,
+            pivot_data,
+            dest_data,
+        )
+    } {
+        exception_t::EXCEPTION_NONE => Ok(()),
+        _ => Err(CNodeError::IllegalOperation),
+    }
+}
+
+/// `seL4_CNode_Delete`
+pub fn cnode_delete(target: SlotAddr) -> Result<(), CNodeError> {
+    cnode_delete_with::<KernelHooks>(target)
+}
+
+/// 同`cnode_delete`，内核回调由`H`提供
+pub fn cnode_delete_with<H: CSpaceHooks>(target: SlotAddr) -> Result<(), CNodeError> {
+    let slot = lookup_slot(&target, false)?;
+    match slot.delete_all_with::<H, _>(&NoLock, true).status {
+        exception_t::EXCEPTION_NONE => Ok(()),
+        status => Err(CNodeError::Interrupted(status)),
+    }
+}
+
+/// `seL4_CNode_Revoke`
+pub fn cnode_revoke(target: SlotAddr) -> Result<(), CNodeError> {
+    cnode_revoke_with::<KernelHooks>(target)
+}
+
+/// 同`cnode_revoke`，内核回调由`H`提供
+pub fn cnode_revoke_with<H: CSpaceHooks>(target: SlotAddr) -> Result<(), CNodeError> {
+    let slot = lookup_slot(&target, false)?;
+    match slot.revoke_with::<H, _>(&NoLock).status {
+        exception_t::EXCEPTION_NONE => Ok(()),
+        status => Err(CNodeError::Interrupted(status)),
+    }
+}
+
+/// `seL4_CNode_SaveCaller`：将当前线程`caller slot`中的`reply_cap`移动到`dest`
+///
+/// `caller_slot`为空时什么也不做，与seL4一致
+#[cfg(not(feature = "kernel_mcs"))]
+pub fn cnode_save_caller(dest: SlotAddr, caller_slot: &mut cte_t) -> Result<(), CNodeError> {
+    let dest_slot = lookup_empty_slot(&dest)?;
+    match caller_slot.capability.get_tag() {
+        cap_tag::cap_null_cap => Ok(()),
+        cap_tag::cap_reply_cap => {
+            let reply_cap = caller_slot.capability.clone();
+            cte_move(&reply_cap, caller_slot, dest_slot);
+            Ok(())
+        }
+        _ => Err(CNodeError::IllegalOperation),
+    }
+}
+
+/// `seL4_CNode_CancelBadgedSends`：要求`endpoint_cap`拥有全部权限，`badge`为0时什么也不做
+pub fn cnode_cancel_badged_sends(target: SlotAddr) -> Result<(), CNodeError> {
+    cnode_cancel_badged_sends_with::<KernelHooks>(target)
+}
+
+/// 同`cnode_cancel_badged_sends`，内核回调由`H`提供
+pub fn cnode_cancel_badged_sends_with<H: CSpaceHooks>(target: SlotAddr) -> Result<(), CNodeError> {
+    let slot = lookup_slot(&target, false)?;
+    if !has_cancel_send_rights(&slot.capability) {
+        return Err(CNodeError::IllegalOperation);
+    }
+    let ep = cap::cap_endpoint_cap(&slot.capability);
+    let badge = ep.get_capEPBadge();
+    if badge != 0 {
+        H::cancel_badged_sends(ep.get_capEPPtr() as usize, badge);
+    }
+    Ok(())
+}
+
+fn has_cancel_send_rights(capability: &cap) -> bool {
+    if capability.get_tag() != cap_tag::cap_endpoint_cap {
+        return false;
+    }
+    let ep = cap::cap_endpoint_cap(capability);
+    ep.get_capCanSend() != 0
+        && ep.get_capCanReceive() != 0
+        && ep.get_capCanGrant() != 0
+        && ep.get_capCanGrantReply() != 0
+}
+
+/// 对应seL4中的`lookupSlotForCNodeOp`
+pub(crate) fn lookup_slot(addr: &SlotAddr, source: bool) -> Result<&'static mut cte_t, CNodeError> {
+    if addr.root.get_tag() != cap_tag::cap_cnode_cap {
+        return Err(CNodeError::FailedLookup {
+            source,
+            fault: LookupFault::InvalidRoot,
+        });
+    }
+    if addr.depth < 1 || addr.depth > WORD_BITS {
+        return Err(CNodeError::RangeError {
+            min: 1,
+            max: WORD_BITS,
+        });
+    }
+    let ret = resolve_address_bits(addr.root, addr.index, addr.depth);
+    if ret.status != exception_t::EXCEPTION_NONE {
+        return Err(CNodeError::FailedLookup {
+            source,
+            fault: ret.lookupFault,
+        });
+    }
+    if ret.bitsRemaining != 0 {
+        return Err(CNodeError::FailedLookup {
+            source,
+            fault: LookupFault::DepthMismatch {
+                bits_found: 0,
+                bits_left: ret.bitsRemaining,
+            },
+        });
+    }
+    Ok(convert_to_mut_type_ref::<cte_t>(ret.slot as usize))
+}
+
+/// 目标`slot`必须为空
+fn lookup_empty_slot(addr: &SlotAddr) -> Result<&'static mut cte_t, CNodeError> {
+    let slot = lookup_slot(addr, false)?;
+    if slot.capability.get_tag() != cap_tag::cap_null_cap {
+        return Err(CNodeError::DeleteFirst);
+    }
+    Ok(slot)
+}
+
+/// 源`slot`中必须有`cap`
+fn lookup_source_cap(addr: &SlotAddr) -> Result<&'static mut cte_t, CNodeError> {
+    let slot = lookup_slot(addr, true)?;
+    if slot.capability.get_tag() == cap_tag::cap_null_cap {
+        return Err(missing_capability(true, addr.depth));
+    }
+    Ok(slot)
+}
+
+#[inline]
+fn missing_capability(source: bool, depth: usize) -> CNodeError {
+    CNodeError::FailedLookup {
+        source,
+        fault: LookupFault::MissingCapability { bits_left: depth },
+    }
+}
+
+/// 派生失败时，`untyped_cap`对应`seL4_RevokeFirst`，其他对应`seL4_IllegalOperation`
+fn derive(src_slot: &cte_t, capability: &cap) -> Result<cap, CNodeError> {
+    let ret = src_slot.derive_cap(capability);
+    if ret.status != exception_t::EXCEPTION_NONE {
+        if capability.get_tag() == cap_tag::cap_untyped_cap {
+            return Err(CNodeError::RevokeFirst);
+        }
+        return Err(CNodeError::IllegalOperation);
+    }
+    if ret.capability.get_tag() == cap_tag::cap_null_cap {
+        return Err(CNodeError::IllegalOperation);
+    }
+    Ok(ret.capability)
+}
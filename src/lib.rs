@@ -1,6 +1,6 @@
 #![feature(core_intrinsics)]
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "host"), no_std)]
+#![cfg_attr(not(feature = "host"), no_main)]
 #![allow(internal_features)]
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
@@ -8,14 +8,26 @@
 #![allow(clippy::clone_on_copy)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::tests::test_runner)]
-#![reexport_test_harness_main = "test_main"]
+#![cfg_attr(not(feature = "host"), reexport_test_harness_main = "test_main")]
 
 #[macro_use]
 extern crate rel4_utils;
 
 pub mod capability;
 mod cte;
+/// `CNode`的各个方法
+pub mod invocation;
+/// 多核下修改派生树时使用的锁
+pub mod lock;
+#[cfg(feature = "lookup_cache")]
+pub mod lookup_cache;
+/// 能力派生树的一致性检查
+pub mod mdb;
+#[cfg(feature = "seqlock_lookup")]
+pub mod seqlock;
 mod structures;
+/// 从`untyped`中创建对象
+pub mod untyped;
 
 /// 需要外部实现的接口
 pub mod deps;
@@ -27,19 +39,35 @@ pub mod compatibility;
 
 pub mod arch;
 
+/// 调试用的`CSpace`导出工具
+pub mod debug;
+
 #[cfg(test)]
 mod tests {
     use capability::same_object_as;
-    use core::arch::global_asm;
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use cte::{cte_insert, cte_move, cte_swap, cte_t, insert_new_cap, resolve_address_bits};
-    use riscv::register::{stvec, utvec::TrapMode};
+    use deps::{CSpaceHooks, UntypedHooks};
+    use lock::NoLock;
+    use sel4_common::structures::exception_t;
+    #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+    use sel4_common::structures_gen::cap_frame_cap;
     use sel4_common::structures_gen::cap_tag;
     use sel4_common::structures_gen::mdb_node;
     use sel4_common::structures_gen::{
-        cap, cap_asid_control_cap, cap_asid_pool_cap, cap_cnode_cap, cap_frame_cap,
-        cap_page_table_cap,
+        cap, cap_asid_control_cap, cap_asid_pool_cap, cap_cnode_cap, cap_page_table_cap,
+    };
+    use sel4_common::utils::convert_to_mut_type_ref;
+    #[cfg(not(feature = "host"))]
+    use sel4_common::{arch::shutdown, println};
+    use structures::FinaliseCapRet;
+    #[cfg(not(feature = "host"))]
+    use {
+        core::arch::global_asm,
+        riscv::register::{stvec, utvec::TrapMode},
     };
-    use sel4_common::{arch::shutdown, println, utils::convert_to_mut_type_ref};
+    #[cfg(not(feature = "host"))]
     global_asm!(include_str!("entry.asm"));
 
     use super::*;
@@ -64,6 +92,39 @@ mod tests {
         println!("-----------------------------------");
     }
 
+    #[test_case]
+    pub fn mask_cap_rights_test() {
+        use capability::mask_cap_rights;
+        use sel4_common::shared_types_bf_gen::seL4_CapRights;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_notification_cap};
+
+        println!("-----------------------------------");
+        println!("Entering mask_cap_rights_test case");
+        // 只保留读权限
+        let rights = seL4_CapRights::new(0, 0, 1, 0);
+        let ep = cap_endpoint_cap::new(7, 1, 1, 1, 1, 0x1000).unsplay();
+        let masked = mask_cap_rights(rights, &ep);
+        let masked_ep = cap::cap_endpoint_cap(&masked);
+        assert_eq!(masked_ep.get_capCanReceive(), 1);
+        assert_eq!(masked_ep.get_capCanSend(), 0);
+        assert_eq!(masked_ep.get_capCanGrant(), 0);
+        assert_eq!(masked_ep.get_capCanGrantReply(), 0);
+        assert_eq!(masked_ep.get_capEPBadge(), 7);
+        assert_eq!(cap::cap_endpoint_cap(&ep).get_capCanSend(), 1);
+
+        let ntfn = cap_notification_cap::new(0, 1, 1, 0x2000).unsplay();
+        let masked = mask_cap_rights(rights, &ntfn);
+        assert_eq!(
+            cap::cap_notification_cap(&masked).get_capNtfnCanReceive(),
+            1
+        );
+        assert_eq!(cap::cap_notification_cap(&masked).get_capNtfnCanSend(), 0);
+
+        let cnode = cap_cnode_cap::new(1, 1, 1, 0x3000).unsplay();
+        assert!(same_object_as(&mask_cap_rights(rights, &cnode), &cnode));
+        println!("Test mask_cap_rights_test passed");
+    }
+
     #[test_case]
     pub fn cte_insert_test() {
         use sel4_common::structures_gen::{cap_asid_control_cap, cap_domain_cap, cap_null_cap};
@@ -256,6 +317,104 @@ mod tests {
         println!("Test cte_swap_test passed");
     }
 
+    #[test_case]
+    pub fn cte_rotate_test() {
+        use cte::cte_rotate;
+        use sel4_common::structures_gen::cap_null_cap;
+
+        println!("-----------------------------------");
+        println!("Entering cte_rotate_test case");
+        let cap1 = cap_cnode_cap::new(0, 0, 2, 0x1000).unsplay();
+        let cap2 = cap_cnode_cap::new(0, 0, 2, 0x2000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut pivot = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut src = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut dest = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        // root -> pivot -> src
+        insert_new_cap(&mut root, &mut pivot, &cap1);
+        cte_insert(&cap2, &mut pivot, &mut src);
+        assert_eq!(src.cteMDBNode.get_mdbRevocable(), 0);
+
+        // pivot -> dest, src -> pivot，dest中的cap设置guard为1，guard_size为2
+        let status = unsafe {
+            cte_rotate(
+                &mut pivot,
+                &mut src as *mut cte_t,
+                &mut dest as *mut cte_t,
+                0,
+                (1 << sel4_common::sel4_config::WORD_RADIX) | 2,
+            )
+        };
+        assert_eq!(status, exception_t::EXCEPTION_NONE);
+        assert_eq!(src.capability.get_tag(), cap_tag::cap_null_cap);
+        assert_eq!(src.cteMDBNode.get_mdbPrev(), 0);
+        assert_eq!(src.cteMDBNode.get_mdbNext(), 0);
+        let moved = cap::cap_cnode_cap(&dest.capability);
+        assert_eq!(moved.get_capCNodePtr(), 0x1000);
+        assert_eq!(moved.get_capCNodeGuard(), 1);
+        assert_eq!(moved.get_capCNodeGuardSize(), 2);
+        assert_eq!(dest.cteMDBNode.get_mdbRevocable(), 1);
+        assert_eq!(dest.cteMDBNode.get_mdbFirstBadged(), 1);
+        assert_eq!(
+            cap::cap_cnode_cap(&pivot.capability).get_capCNodePtr(),
+            0x2000
+        );
+        assert_eq!(pivot.cteMDBNode.get_mdbRevocable(), 0);
+        // root -> dest -> pivot
+        assert_eq!(root.cteMDBNode.get_mdbNext() as usize, dest.get_ptr());
+        assert_eq!(dest.cteMDBNode.get_mdbPrev() as usize, root.get_ptr());
+        assert_eq!(dest.cteMDBNode.get_mdbNext() as usize, pivot.get_ptr());
+        assert_eq!(pivot.cteMDBNode.get_mdbPrev() as usize, dest.get_ptr());
+        assert_eq!(pivot.cteMDBNode.get_mdbNext(), 0);
+
+        // src与dest相同且与pivot相邻：root -> dest -> pivot 变为 root -> pivot -> dest
+        let pivot_ptr = &mut pivot as *mut cte_t;
+        let status = unsafe { cte_rotate(&mut dest, pivot_ptr, pivot_ptr, 0, 0) };
+        assert_eq!(status, exception_t::EXCEPTION_NONE);
+        assert_eq!(
+            cap::cap_cnode_cap(&pivot.capability).get_capCNodePtr(),
+            0x1000
+        );
+        assert_eq!(pivot.cteMDBNode.get_mdbRevocable(), 1);
+        assert_eq!(
+            cap::cap_cnode_cap(&dest.capability).get_capCNodePtr(),
+            0x2000
+        );
+        assert_eq!(dest.cteMDBNode.get_mdbRevocable(), 0);
+        assert_eq!(root.cteMDBNode.get_mdbNext() as usize, pivot.get_ptr());
+        assert_eq!(pivot.cteMDBNode.get_mdbPrev() as usize, root.get_ptr());
+        assert_eq!(pivot.cteMDBNode.get_mdbNext() as usize, dest.get_ptr());
+        assert_eq!(dest.cteMDBNode.get_mdbPrev() as usize, pivot.get_ptr());
+        assert_eq!(dest.cteMDBNode.get_mdbNext(), 0);
+
+        // pivot与src或dest相同时直接拒绝，派生树保持不变
+        let dest_ptr = &mut dest as *mut cte_t;
+        let src_ptr = &mut src as *mut cte_t;
+        let status = unsafe { cte_rotate(&mut *dest_ptr, dest_ptr, src_ptr, 0, 0) };
+        assert_eq!(status, exception_t::EXCEPTION_SYSCALL_ERROR);
+        let status = unsafe { cte_rotate(&mut *dest_ptr, pivot_ptr, dest_ptr, 0, 0) };
+        assert_eq!(status, exception_t::EXCEPTION_SYSCALL_ERROR);
+        assert_eq!(
+            cap::cap_cnode_cap(&dest.capability).get_capCNodePtr(),
+            0x2000
+        );
+        assert_eq!(pivot.cteMDBNode.get_mdbNext() as usize, dest.get_ptr());
+        assert_eq!(dest.cteMDBNode.get_mdbNext(), 0);
+        println!("Test cte_rotate_test passed");
+    }
+
     #[test_case]
     pub fn insert_new_cap_test() {
         use sel4_common::structures_gen::{cap_asid_control_cap, cap_domain_cap, cap_null_cap};
@@ -344,6 +503,322 @@ mod tests {
         println!("Test resolve_address_bits_test passed");
     }
 
+    #[test_case]
+    pub fn resolve_address_bits_fault_test() {
+        use crate::structures::LookupFault;
+        use sel4_common::structures_gen::{cap_cnode_cap, cap_domain_cap};
+
+        println!("-----------------------------------");
+        println!("Entering resolve_address_bits_fault_test case");
+        let buffer: [u8; 1024] = [0; 1024];
+        let cap1 = cap_cnode_cap::new(2, 2, 3, buffer.as_ptr() as u64).unsplay();
+        let res_ret = resolve_address_bits(&cap1, 1 << 3, 5);
+        assert_eq!(res_ret.status, exception_t::EXCEPTION_LOOKUP_FAULT);
+        assert_eq!(
+            res_ret.lookupFault,
+            LookupFault::GuardMismatch {
+                guard_found: 1,
+                guard_expected: 2,
+                bits_left: 5,
+                guard_size: 2,
+            }
+        );
+        let res_ret = resolve_address_bits(&cap1, 2 << 1, 3);
+        assert_eq!(
+            res_ret.lookupFault,
+            LookupFault::DepthMismatch {
+                bits_found: 5,
+                bits_left: 3,
+            }
+        );
+        let res_ret = resolve_address_bits(&cap_domain_cap::new().unsplay(), 0, 5);
+        assert_eq!(res_ret.lookupFault, LookupFault::InvalidRoot);
+        println!("Test resolve_address_bits_fault_test passed");
+    }
+
+    #[cfg(feature = "lookup_cache")]
+    #[test_case]
+    pub fn lookup_cache_test() {
+        use sel4_common::structures_gen::{cap_domain_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering lookup_cache_test case");
+        let level1: [cte_t; 8] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let level2: [cte_t; 8] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let cap1 = cap_cnode_cap::new(0, 0, 3, level1.as_ptr() as u64).unsplay();
+        let cap2 = cap_cnode_cap::new(0, 0, 3, level2.as_ptr() as u64).unsplay();
+        let mut parent = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(
+            &mut parent,
+            convert_to_mut_type_ref(level1[2].get_ptr()),
+            &cap2,
+        );
+        insert_new_cap(
+            &mut parent,
+            convert_to_mut_type_ref(level2[5].get_ptr()),
+            &cap_domain_cap::new().unsplay(),
+        );
+        let cap_ptr = (2 << 3) | 5;
+        for _ in 0..2 {
+            let res_ret = resolve_address_bits(&cap1, cap_ptr, 6);
+            assert_eq!(res_ret.slot as usize, level2[5].get_ptr());
+        }
+        cte_move(
+            &cap2,
+            convert_to_mut_type_ref(level1[2].get_ptr()),
+            convert_to_mut_type_ref(level1[3].get_ptr()),
+        );
+        let res_ret = resolve_address_bits(&cap1, cap_ptr, 6);
+        assert_eq!(res_ret.slot as usize, level1[2].get_ptr());
+        assert_eq!(res_ret.bitsRemaining, 3);
+        println!("Test lookup_cache_test passed");
+    }
+
+    /// 一个线程不断交换两个`CNode cap`，其他线程无锁查找，结果必须与某一时刻的`CSpace`一致
+    #[cfg(all(feature = "host", feature = "seqlock_lookup"))]
+    #[test_case]
+    pub fn seqlock_lookup_test() {
+        use crate::seqlock::resolve_address_bits_lockfree;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering seqlock_lookup_test case");
+        let new_cnode = || -> [cte_t; 8] {
+            core::array::from_fn(|_| cte_t {
+                capability: cap_null_cap::new().unsplay(),
+                cteMDBNode: mdb_node::new(0, 0, 0, 0),
+            })
+        };
+        let (level1, node_a, node_b) = (new_cnode(), new_cnode(), new_cnode());
+        let mut parent = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let slot =
+            |cnode: &[cte_t; 8], i: usize| convert_to_mut_type_ref::<cte_t>(cnode[i].get_ptr());
+        let root = cap_cnode_cap::new(0, 0, 3, level1.as_ptr() as u64).unsplay();
+        for (i, node, badge) in [(2, &node_a, 1), (3, &node_b, 2)] {
+            let cnode = cap_cnode_cap::new(0, 0, 3, node.as_ptr() as u64).unsplay();
+            insert_new_cap(&mut parent, slot(&level1, i), &cnode);
+            let ep = cap_endpoint_cap::new(badge, 1, 1, 1, 1, 0x1000 * badge).unsplay();
+            insert_new_cap(&mut parent, slot(node, 5), &ep);
+        }
+        let cap_ptr = (2 << 3) | 5;
+
+        let snapshot = resolve_address_bits_lockfree(&root, cap_ptr, 6);
+        assert_eq!(snapshot.ret.slot as usize, node_a[5].get_ptr());
+        assert_eq!(
+            cap::cap_endpoint_cap(&snapshot.capability).get_capEPBadge(),
+            1
+        );
+        let snapshot = resolve_address_bits_lockfree(&root, (4 << 3) | 5, 6);
+        assert_eq!(snapshot.ret.slot as usize, level1[4].get_ptr());
+        assert_eq!(snapshot.ret.bitsRemaining, 3);
+        assert_eq!(snapshot.capability.get_tag(), cap_tag::cap_null_cap);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..2000 {
+                    let (a, b) = (slot(&level1, 2), slot(&level1, 3));
+                    let (cap_a, cap_b) = (a.capability.clone(), b.capability.clone());
+                    cte_swap(&cap_a, a, &cap_b, b);
+                }
+            });
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..2000 {
+                        let snapshot = resolve_address_bits_lockfree(&root, cap_ptr, 6);
+                        let badge = cap::cap_endpoint_cap(&snapshot.capability).get_capEPBadge();
+                        let expected = match badge {
+                            1 => node_a[5].get_ptr(),
+                            2 => node_b[5].get_ptr(),
+                            _ => panic!("torn lookup: badge {}", badge),
+                        };
+                        assert_eq!(snapshot.ret.slot as usize, expected);
+                        assert_eq!(snapshot.ret.bitsRemaining, 0);
+                    }
+                });
+            }
+        });
+        println!("Test seqlock_lookup_test passed");
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    #[test_case]
+    pub fn canonical_address_test() {
+        use crate::arch::{canonical_address, sign_extend, VA_BITS};
+        use crate::cte::mdb_ptr_func;
+
+        println!("-----------------------------------");
+        println!("Entering canonical_address_test case");
+        let expected = if cfg!(feature = "riscv_sv57") {
+            57
+        } else if cfg!(feature = "riscv_sv48") {
+            48
+        } else {
+            39
+        };
+        assert_eq!(VA_BITS, expected);
+        for va_bits in [39, 48, 57] {
+            let top = 1usize << (va_bits - 1);
+            assert_eq!(sign_extend(top, va_bits), !(top - 1));
+            assert_eq!(sign_extend(top | 0x1000, va_bits), !(top - 1) | 0x1000);
+            assert_eq!(sign_extend(top - 0x1000, va_bits), top - 0x1000);
+            assert_eq!(
+                sign_extend(!(top - 1) | 0x2000, va_bits),
+                !(top - 1) | 0x2000
+            );
+        }
+        let top = 1usize << (VA_BITS - 1);
+        assert_eq!(canonical_address(top | 0x1000), !(top - 1) | 0x1000);
+
+        // 超出Sv39位域的内核地址也要能从mdb_node中原样读回，标志位不受影响
+        let ptr = !(top - 1) | 0x10_2040;
+        let mut node = mdb_node::new(0, 1, 1, 0);
+        node.set_next_ptr(ptr);
+        node.set_prev_ptr(ptr);
+        assert_eq!(node.get_next_ptr(), ptr);
+        assert_eq!(node.get_prev_ptr(), ptr);
+        assert_eq!(node.get_mdbRevocable(), 1);
+        assert_eq!(node.get_mdbFirstBadged(), 1);
+        node.set_next_ptr(0);
+        assert_eq!(node.get_next_ptr(), 0);
+        assert_eq!(node.get_mdbRevocable(), 1);
+        println!("Test canonical_address_test passed");
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test_case]
+    pub fn kernel_window_test() {
+        use crate::arch::{KernelLevel, KernelWindow, EL2_WINDOW_BASE};
+
+        println!("-----------------------------------");
+        println!("Entering kernel_window_test case");
+        let el1 = KernelWindow {
+            va_bits: 48,
+            level: KernelLevel::EL1,
+        };
+        let ptr = 0xffff_ff80_0010_2040usize;
+        assert!(el1.contains(ptr));
+        // 低2位是revocable和firstBadged
+        assert_eq!(el1.decode(0xff80_0010_2040 | 0b11), ptr);
+        assert_eq!(el1.decode(ptr | 0b10), ptr);
+        assert_eq!(el1.decode(0x0000_0080_0010_2040), 0x0000_0080_0010_2040);
+        assert_eq!(el1.decode(0), 0);
+        assert!(el1.contains(0));
+        assert!(!el1.contains(0x0001_0000_0010_2040));
+        assert!(!el1.contains(ptr | 0b01));
+
+        let el1_52 = KernelWindow {
+            va_bits: 52,
+            level: KernelLevel::EL1,
+        };
+        let ptr = 0xfff8_0000_0010_2040usize;
+        assert!(el1_52.contains(ptr));
+        assert_eq!(el1_52.decode(0x8_0000_0010_2040), ptr);
+
+        let el2 = KernelWindow {
+            va_bits: 48,
+            level: KernelLevel::EL2,
+        };
+        let ptr = 0x0000_0080_0010_2040usize;
+        assert!(el2.contains(ptr));
+        assert_eq!(el2.decode(ptr | 0b01), ptr);
+        // 与原来的实现一致：第46位置位时补上窗口基址
+        let raw = 0x0000_4000_0010_2040usize;
+        assert_eq!(el2.decode(raw), raw | 0x80_0000_0000);
+        assert!(!el2.contains(raw));
+        assert!(el2.contains(0x0000_8000_0010_2040));
+
+        // 窗口基址不随va_bits变化，52位时第50位置位才补上基址
+        let el2_52 = KernelWindow {
+            va_bits: 52,
+            level: KernelLevel::EL2,
+        };
+        let ptr = EL2_WINDOW_BASE | 0x10_2040;
+        assert!(el2_52.contains(ptr));
+        assert_eq!(el2_52.decode(el2_52.encode(ptr) | 0b11), ptr);
+        let raw = 0x0004_0000_0010_2040usize;
+        assert_eq!(el2_52.decode(raw), raw | EL2_WINDOW_BASE);
+        assert!(!el2_52.contains(0x0010_0080_0010_2040));
+
+        // 编码后再解码得到原来的指针，标志位不影响结果
+        for (window, ptr) in [
+            (el1, 0xffff_ff80_0010_2040usize),
+            (el1_52, 0xfff8_0000_0010_2040),
+            (el2, EL2_WINDOW_BASE | 0x10_2040),
+            (el2_52, EL2_WINDOW_BASE | 0x10_2040),
+        ] {
+            let raw = window.encode(ptr);
+            assert_eq!(raw & !mask_bits!(window.va_bits), 0);
+            assert_eq!(window.decode(raw), ptr);
+            assert_eq!(window.decode(raw | 0b11), ptr);
+        }
+
+        // 内核实际使用的窗口
+        #[cfg(target_arch = "aarch64")]
+        {
+            use crate::arch::{decode_mdb_ptr, encode_mdb_ptr, KERNEL_WINDOW};
+            use crate::cte::mdb_ptr_func;
+
+            let va_bits = if cfg!(feature = "aarch64_va52") {
+                52
+            } else {
+                48
+            };
+            assert_eq!(KERNEL_WINDOW.va_bits, va_bits);
+            let ptr = if cfg!(feature = "hypervisor") {
+                assert_eq!(KERNEL_WINDOW.level, KernelLevel::EL2);
+                EL2_WINDOW_BASE | 0x10_2040
+            } else {
+                assert_eq!(KERNEL_WINDOW.level, KernelLevel::EL1);
+                !mask_bits!(va_bits - 1) | 0x10_2040
+            };
+            assert!(KERNEL_WINDOW.contains(ptr));
+            assert_eq!(decode_mdb_ptr(encode_mdb_ptr(ptr) | 0b11), ptr);
+            let mut node = mdb_node::new(0, 1, 0, 0);
+            node.set_next_ptr(ptr);
+            node.set_prev_ptr(ptr);
+            assert_eq!(node.get_next_ptr(), ptr);
+            assert_eq!(node.get_prev_ptr(), ptr);
+            assert_eq!(node.get_mdbRevocable(), 1);
+            assert_eq!(node.get_mdbFirstBadged(), 0);
+        }
+        println!("Test kernel_window_test passed");
+    }
+
+    #[test_case]
+    pub fn zombie_id_test() {
+        use crate::capability::zombie::{zombie_func, zombie_new};
+        use sel4_common::sel4_config::WORD_BITS;
+
+        println!("-----------------------------------");
+        println!("Entering zombie_id_test case");
+        let zombie = zombie_new(3, 8, 0x12345);
+        let z = cap::cap_zombie_cap(&zombie);
+        assert_eq!(z.get_zombie_ptr(), 0x12200);
+        assert_eq!(z.get_zombie_number(), 3);
+        z.set_zombie_number(5);
+        assert_eq!(z.get_zombie_ptr(), 0x12200);
+        assert_eq!(z.get_zombie_number(), 5);
+
+        // radix + 1 达到字宽时整个ID都是编号
+        let zombie = zombie_new(7, WORD_BITS - 1, 0x12345);
+        let z = cap::cap_zombie_cap(&zombie);
+        assert_eq!(z.get_zombie_ptr(), 0);
+        assert_eq!(z.get_zombie_number(), 7);
+        println!("Test zombie_id_test passed");
+    }
+
     #[test_case]
     pub fn cap_t_create_happy_test() {
         use sel4_common::structures_gen::cap_cnode_cap;
@@ -356,6 +831,36 @@ mod tests {
         println!("Test cap_t_create_happy_test passed");
     }
 
+    #[test_case]
+    pub fn typed_cap_test() {
+        use crate::capability::typed::{CNode, Cap, Endpoint, WrongCapType};
+        use sel4_common::structures_gen::{cap_cnode_cap, cap_endpoint_cap};
+
+        println!("-----------------------------------");
+        println!("Entering typed_cap_test case");
+        let ep = cap_endpoint_cap::new(42, 0, 1, 1, 0, 0x1000).unsplay();
+        let typed = Cap::<Endpoint>::try_from(&ep).unwrap();
+        assert_eq!(typed.badge(), 42);
+        assert_eq!(typed.ptr(), 0x1000);
+        let rights = typed.rights();
+        assert_eq!(rights.get_capAllowGrant(), 1);
+        assert_eq!(rights.get_capAllowWrite(), 1);
+        assert_eq!(rights.get_capAllowRead(), 0);
+        assert_eq!(
+            Cap::<CNode>::try_from(&ep).err(),
+            Some(WrongCapType {
+                expected: cap_tag::cap_cnode_cap,
+                found: cap_tag::cap_endpoint_cap,
+            })
+        );
+        let cnode = cap_cnode_cap::new(3, 2, 4, 0x2000).unsplay();
+        let typed = Cap::<CNode>::try_from(&cnode).unwrap();
+        assert_eq!(typed.guard(), 3);
+        assert_eq!(typed.guard_size(), 2);
+        assert_eq!(typed.radix(), 4);
+        println!("Test typed_cap_test passed");
+    }
+
     #[test_case]
     pub fn slot_get_ptr_happy_case_test() {
         println!("-----------------------------------");
@@ -373,79 +878,1346 @@ mod tests {
     }
 
     #[test_case]
-    pub fn shutdown_test() {
-        println!("All Test Cases passed, shutdown");
-        shutdown();
-    }
+    pub fn delete_all_test() {
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
 
-    fn new_mock_slot(tag: u64) -> cte_t {
-        match tag {
-            cap_tag::cap_cnode_cap => {
-                let capability = cap_cnode_cap::new(0, 0, 0, 0);
-                cte_t {
-                    capability: capability.unsplay(),
-                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
-                }
-            }
-            cap_tag::cap_frame_cap => {
-                let capability = cap_frame_cap::new(0, 0, 0, 0, 0, 0);
-                cte_t {
-                    capability: capability.unsplay(),
-                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
-                }
-            }
-            cap_tag::cap_page_table_cap => {
-                let capability = cap_page_table_cap::new(0, 0, 0, 0);
-                cte_t {
-                    capability: capability.unsplay(),
-                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
-                }
-            }
-            cap_tag::cap_asid_control_cap => {
-                let capability = cap_asid_control_cap::new();
-                cte_t {
-                    capability: capability.unsplay(),
-                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
-                }
-            }
-            cap_tag::cap_asid_pool_cap => {
-                let capability = cap_asid_pool_cap::new(0, 0);
-                cte_t {
-                    capability: capability.unsplay(),
-                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
-                }
-            }
-            _ => panic!("Invalid cap tag"),
-        }
+        println!("-----------------------------------");
+        println!("Entering delete_all_test case");
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte1 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte2 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut cte1, &ep);
+        cte_insert(&ep, &mut cte1, &mut cte2);
+        assert_eq!(cte1.delete_all(true), exception_t::EXCEPTION_NONE);
+        assert_eq!(cte1.capability.get_tag(), cap_tag::cap_null_cap);
+        assert_eq!(cte1.cteMDBNode.get_mdbNext(), 0);
+        assert_eq!(cte1.cteMDBNode.get_mdbPrev(), 0);
+        assert_eq!(
+            root.cteMDBNode.get_mdbNext(),
+            &mut cte2 as *mut cte_t as u64
+        );
+        assert_eq!(
+            cte2.cteMDBNode.get_mdbPrev(),
+            &mut root as *mut cte_t as u64
+        );
+        println!("Test delete_all_test passed");
     }
 
-    #[panic_handler]
-    fn panic(info: &core::panic::PanicInfo) -> ! {
-        println!("{}", info);
-        shutdown()
-    }
+    #[test_case]
+    pub fn revoke_test() {
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
 
-    pub fn test_runner(tests: &[&dyn Fn()]) {
-        println!("Running {} tests", tests.len());
-        for test in tests {
-            test();
-        }
+        println!("-----------------------------------");
+        println!("Entering revoke_test case");
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let badged = cap_endpoint_cap::new(42, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte1 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte2 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte3 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut cte1, &ep);
+        cte_insert(&badged, &mut cte1, &mut cte2);
+        cte_insert(&ep, &mut cte1, &mut cte3);
+        assert_eq!(cte1.revoke(), exception_t::EXCEPTION_NONE);
+        assert_eq!(cte1.capability.get_tag(), cap_tag::cap_endpoint_cap);
+        assert_eq!(cte2.capability.get_tag(), cap_tag::cap_null_cap);
+        assert_eq!(cte3.capability.get_tag(), cap_tag::cap_null_cap);
+        assert_eq!(cte1.cteMDBNode.get_mdbNext(), 0);
+        assert_eq!(
+            cte1.cteMDBNode.get_mdbPrev(),
+            &mut root as *mut cte_t as u64
+        );
+        println!("Test revoke_test passed");
     }
 
-    #[no_mangle]
-    pub fn call_test_main() {
-        extern "C" {
-            fn trap_entry();
-        }
-        unsafe {
-            stvec::write(trap_entry as usize, TrapMode::Direct);
-        }
-        crate::test_main();
+    #[test_case]
+    pub fn revoke_with_hooks_test() {
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering revoke_with_hooks_test case");
+        TestHooks::reset();
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte1 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte2 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte3 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut cte1, &ep);
+        cte_insert(&ep, &mut cte1, &mut cte2);
+        cte_insert(&ep, &mut cte1, &mut cte3);
+        assert_eq!(
+            cte1.revoke_with::<TestHooks, _>(&NoLock).status,
+            exception_t::EXCEPTION_NONE
+        );
+        assert_eq!(TestHooks::deleted(), 2);
+        assert_eq!(cte1.cteMDBNode.get_mdbNext(), 0);
+        println!("Test revoke_with_hooks_test passed");
     }
-    #[no_mangle]
-    pub fn c_handle_syscall() {
-        unsafe {
-            core::arch::asm!("sret");
+
+    #[test_case]
+    pub fn cnode_copy_mint_test() {
+        use crate::invocation::{cnode_copy, cnode_mint, CNodeError, SlotAddr};
+        use crate::structures::LookupFault;
+        use sel4_common::shared_types_bf_gen::seL4_CapRights;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering cnode_copy_mint_test case");
+        // 8个slot的CNode，guard为0，深度为3
+        let mut slots: [cte_t; 8] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let cnode = cap_cnode_cap::new(0, 0, 3, slots.as_mut_ptr() as u64).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        insert_new_cap(&mut root, &mut slots[0], &ep);
+        let read_only = seL4_CapRights::new(0, 0, 1, 0);
+        let all = seL4_CapRights::new(1, 1, 1, 1);
+
+        assert_eq!(
+            cnode_copy(
+                SlotAddr::new(&cnode, 1, 3),
+                SlotAddr::new(&cnode, 0, 3),
+                read_only
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            cap::cap_endpoint_cap(&slots[1].capability).get_capCanSend(),
+            0
+        );
+        assert_eq!(
+            cap::cap_endpoint_cap(&slots[1].capability).get_capCanReceive(),
+            1
+        );
+        assert_eq!(
+            slots[1].cteMDBNode.get_mdbPrev() as usize,
+            slots[0].get_ptr()
+        );
+
+        assert_eq!(
+            cnode_mint(
+                SlotAddr::new(&cnode, 2, 3),
+                SlotAddr::new(&cnode, 0, 3),
+                all,
+                5
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            cap::cap_endpoint_cap(&slots[2].capability).get_capEPBadge(),
+            5
+        );
+        assert_eq!(slots[2].cteMDBNode.get_mdbRevocable(), 1);
+
+        assert_eq!(
+            cnode_copy(
+                SlotAddr::new(&cnode, 1, 3),
+                SlotAddr::new(&cnode, 0, 3),
+                all
+            ),
+            Err(CNodeError::DeleteFirst)
+        );
+        assert_eq!(
+            cnode_copy(
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 3, 3),
+                all
+            ),
+            Err(CNodeError::FailedLookup {
+                source: true,
+                fault: LookupFault::MissingCapability { bits_left: 3 },
+            })
+        );
+        // 已经带有badge的cap不能再次设置badge
+        assert_eq!(
+            cnode_mint(
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 2, 3),
+                all,
+                6
+            ),
+            Err(CNodeError::IllegalOperation)
+        );
+        assert_eq!(
+            cnode_copy(
+                SlotAddr::new(&cnode, 4, 0),
+                SlotAddr::new(&cnode, 0, 3),
+                all
+            ),
+            Err(CNodeError::RangeError {
+                min: 1,
+                max: sel4_common::sel4_config::WORD_BITS,
+            })
+        );
+        println!("Test cnode_copy_mint_test passed");
+    }
+
+    #[test_case]
+    pub fn cnode_move_rotate_test() {
+        use crate::invocation::{
+            cnode_cancel_badged_sends, cnode_move, cnode_mutate, cnode_revoke, cnode_rotate,
+            CNodeError, SlotAddr,
+        };
+        use crate::structures::LookupFault;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering cnode_move_rotate_test case");
+        let mut slots: [cte_t; 8] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let cnode = cap_cnode_cap::new(0, 0, 3, slots.as_mut_ptr() as u64).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let cnode1 = cap_cnode_cap::new(0, 0, 2, 0x3000).unsplay();
+        let cnode2 = cap_cnode_cap::new(0, 0, 2, 0x4000).unsplay();
+        insert_new_cap(&mut root, &mut slots[0], &ep);
+        insert_new_cap(&mut root, &mut slots[5], &cnode1);
+        insert_new_cap(&mut root, &mut slots[6], &cnode2);
+
+        assert_eq!(
+            cnode_move(SlotAddr::new(&cnode, 2, 3), SlotAddr::new(&cnode, 0, 3)),
+            Ok(())
+        );
+        assert_eq!(slots[0].capability.get_tag(), cap_tag::cap_null_cap);
+        assert_eq!(
+            cap::cap_endpoint_cap(&slots[2].capability).get_capEPPtr(),
+            0x1000
+        );
+        // endpoint不能通过Mutate修改badge
+        assert_eq!(
+            cnode_mutate(SlotAddr::new(&cnode, 3, 3), SlotAddr::new(&cnode, 2, 3), 9),
+            Err(CNodeError::IllegalOperation)
+        );
+
+        // guard为1，guard_size为2
+        assert_eq!(
+            cnode_mutate(
+                SlotAddr::new(&cnode, 3, 3),
+                SlotAddr::new(&cnode, 5, 3),
+                (1 << sel4_common::sel4_config::WORD_RADIX) | 2
+            ),
+            Ok(())
+        );
+        let mutated = cap::cap_cnode_cap(&slots[3].capability);
+        assert_eq!(mutated.get_capCNodeGuard(), 1);
+        assert_eq!(mutated.get_capCNodeGuardSize(), 2);
+        assert_eq!(slots[5].capability.get_tag(), cap_tag::cap_null_cap);
+
+        // slot3 -> slot6 -> slot4
+        assert_eq!(
+            cnode_rotate(
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 6, 3),
+                SlotAddr::new(&cnode, 3, 3),
+                0,
+                0
+            ),
+            Ok(())
+        );
+        assert_eq!(slots[3].capability.get_tag(), cap_tag::cap_null_cap);
+        let pivot = cap::cap_cnode_cap(&slots[6].capability);
+        assert_eq!(pivot.get_capCNodePtr(), 0x3000);
+        assert_eq!(pivot.get_capCNodeGuardSize(), 0);
+        assert_eq!(
+            cap::cap_cnode_cap(&slots[4].capability).get_capCNodePtr(),
+            0x4000
+        );
+        assert_eq!(
+            cnode_rotate(
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 6, 3),
+                0,
+                0
+            ),
+            Err(CNodeError::IllegalOperation)
+        );
+        // pivot与seL4的lookupPivotSlot一样按源slot报告查找错误
+        assert_eq!(
+            cnode_rotate(
+                SlotAddr::new(&cnode, 4, 3),
+                SlotAddr::new(&cnode, 6, 2),
+                SlotAddr::new(&cnode, 3, 3),
+                0,
+                0
+            ),
+            Err(CNodeError::FailedLookup {
+                source: true,
+                fault: LookupFault::DepthMismatch {
+                    bits_found: 3,
+                    bits_left: 2,
+                },
+            })
+        );
+
+        assert_eq!(
+            cnode_cancel_badged_sends(SlotAddr::new(&cnode, 2, 3)),
+            Ok(())
+        );
+        assert_eq!(
+            cnode_cancel_badged_sends(SlotAddr::new(&cnode, 7, 3)),
+            Err(CNodeError::IllegalOperation)
+        );
+        assert_eq!(cnode_revoke(SlotAddr::new(&cnode, 2, 3)), Ok(()));
+        assert_eq!(slots[2].capability.get_tag(), cap_tag::cap_endpoint_cap);
+        println!("Test cnode_move_rotate_test passed");
+    }
+
+    #[test_case]
+    pub fn untyped_retype_test() {
+        use crate::invocation::{CNodeError, SlotAddr};
+        use crate::untyped::{
+            untyped_retype_with, SEL4_CAP_TABLE_OBJECT, SEL4_ENDPOINT_OBJECT, SEL4_UNTYPED_OBJECT,
+        };
+        use sel4_common::sel4_config::{
+            SEL4_ENDPOINT_BITS, SEL4_MAX_UNTYPED_BITS, SEL4_MIN_UNTYPED_BITS, WORD_BITS,
+        };
+        use sel4_common::structures_gen::{cap_null_cap, cap_untyped_cap};
+
+        println!("-----------------------------------");
+        println!("Entering untyped_retype_test case");
+        TestHooks::reset();
+        let mut slots: [cte_t; 8] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let cnode = cap_cnode_cap::new(0, 0, 3, slots.as_mut_ptr() as u64).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut ut_slot = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        // 12位大小的untyped，已经分配了1个最小块
+        let ut_base = 0x10000usize;
+        let ut = cap_untyped_cap::new(1, 0, 12, ut_base as u64).unsplay();
+        insert_new_cap(&mut root, &mut ut_slot, &ut);
+        let ep_size = 1usize << SEL4_ENDPOINT_BITS;
+
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                SEL4_ENDPOINT_OBJECT,
+                0,
+                SlotAddr::new(&cnode, 0, 0),
+                2,
+                3
+            ),
+            Ok(())
+        );
+        // 没有子节点时从头分配
+        for i in 0..3 {
+            assert_eq!(
+                cap::cap_endpoint_cap(&slots[2 + i].capability).get_capEPPtr() as usize,
+                ut_base + i * ep_size
+            );
+        }
+        assert_eq!(
+            cap::cap_untyped_cap(&ut_slot.capability).get_capFreeIndex() as usize,
+            (3 * ep_size) >> SEL4_MIN_UNTYPED_BITS
+        );
+        assert_eq!(ut_slot.children().count(), 3);
+
+        // 有子节点时从capFreeIndex继续分配
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                SEL4_ENDPOINT_OBJECT,
+                0,
+                SlotAddr::new(&cnode, 0, 0),
+                5,
+                1
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            cap::cap_endpoint_cap(&slots[5].capability).get_capEPPtr() as usize,
+            ut_base + 3 * ep_size
+        );
+
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                SEL4_ENDPOINT_OBJECT,
+                0,
+                SlotAddr::new(&cnode, 0, 0),
+                2,
+                1
+            ),
+            Err(CNodeError::DeleteFirst)
+        );
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                SEL4_ENDPOINT_OBJECT,
+                0,
+                SlotAddr::new(&cnode, 0, 0),
+                6,
+                3
+            ),
+            Err(CNodeError::RangeError { min: 1, max: 2 })
+        );
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                100,
+                0,
+                SlotAddr::new(&cnode, 0, 0),
+                6,
+                1
+            ),
+            Err(CNodeError::InvalidArgument { index: 0 })
+        );
+        // 先检查对象类型，再检查大小
+        assert_eq!(
+            untyped_retype_with::<TestHooks>(
+                &mut ut_slot,
+                100,
+                usize::MAX,
+                SlotAddr::new(&cnode, 0, 0),
+                6,
+                1
+            ),
+            Err(CNodeError::InvalidArgument { index: 0 })
+        );
+        // 过大的user_size与过大的对象一样返回RangeError
+        for (obj_type, user_size) in [
+            (SEL4_CAP_TABLE_OBJECT, usize::MAX),
+            (SEL4_CAP_TABLE_OBJECT, WORD_BITS),
+            (SEL4_UNTYPED_OBJECT, SEL4_MAX_UNTYPED_BITS + 1),
+        ] {
+            assert_eq!(
+                untyped_retype_with::<TestHooks>(
+                    &mut ut_slot,
+                    obj_type,
+                    user_size,
+                    SlotAddr::new(&cnode, 0, 0),
+                    6,
+                    1
+                ),
+                Err(CNodeError::RangeError {
+                    min: 0,
+                    max: SEL4_MAX_UNTYPED_BITS
+                })
+            );
+        }
+        println!("Test untyped_retype_test passed");
+    }
+
+    #[test_case]
+    pub fn reset_untyped_test() {
+        use crate::untyped::RESET_CHUNK_BITS;
+        use sel4_common::sel4_config::SEL4_MIN_UNTYPED_BITS;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap, cap_untyped_cap};
+
+        println!("-----------------------------------");
+        println!("Entering reset_untyped_test case");
+        TestHooks::reset();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut ut_slot = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut child = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        // 12位的untyped已经全部分配
+        let full = (1usize << 12) >> SEL4_MIN_UNTYPED_BITS;
+        let ut = cap_untyped_cap::new(full as u64, 0, 12, 0x10000).unsplay();
+        insert_new_cap(&mut root, &mut ut_slot, &ut);
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x10000).unsplay();
+        insert_new_cap(&mut ut_slot, &mut child, &ep);
+        assert_eq!(
+            ut_slot.reset_untyped_with::<TestHooks>(),
+            exception_t::EXCEPTION_SYSCALL_ERROR
+        );
+        child.delete_one_with::<TestHooks, _>(&NoLock);
+
+        // 第一块清零之后被抢占
+        TestHooks::preempt_next();
+        assert_eq!(
+            ut_slot.reset_untyped_with::<TestHooks>(),
+            exception_t::EXCEPTION_PREEMTED
+        );
+        let chunk = 1usize << RESET_CHUNK_BITS;
+        assert_eq!(TestHooks::cleared(), chunk);
+        assert_eq!(
+            cap::cap_untyped_cap(&ut_slot.capability).get_capFreeIndex() as usize,
+            ((1 << 12) - chunk) >> SEL4_MIN_UNTYPED_BITS
+        );
+
+        // 再次调用从上次停下的位置继续
+        assert_eq!(
+            ut_slot.reset_untyped_with::<TestHooks>(),
+            exception_t::EXCEPTION_NONE
+        );
+        assert_eq!(TestHooks::cleared(), 1 << 12);
+        assert_eq!(
+            cap::cap_untyped_cap(&ut_slot.capability).get_capFreeIndex(),
+            0
+        );
+        println!("Test reset_untyped_test passed");
+    }
+
+    #[test_case]
+    pub fn revoke_progress_test() {
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering revoke_progress_test case");
+        TestHooks::reset();
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut parent = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut child1 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut child2 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut parent, &ep);
+        cte_insert(&ep, &mut parent, &mut child1);
+        cte_insert(&ep, &mut parent, &mut child2);
+
+        // 删除第一个子节点之后被抢占
+        TestHooks::preempt_next();
+        let progress = parent.revoke_with::<TestHooks, _>(&NoLock);
+        assert_eq!(progress.status, exception_t::EXCEPTION_PREEMTED);
+        assert_eq!(progress.slots_deleted, 1);
+        assert_eq!(progress.in_progress, child1.get_ptr());
+        assert!(!progress.is_complete());
+
+        let progress = parent.revoke_with::<TestHooks, _>(&NoLock);
+        assert!(progress.is_complete());
+        assert_eq!(progress.slots_deleted, 1);
+        assert_eq!(parent.cteMDBNode.get_mdbNext(), 0);
+
+        let progress = parent.delete_all_with::<TestHooks, _>(&NoLock, true);
+        assert!(progress.is_complete());
+        assert_eq!(progress.slots_deleted, 1);
+        assert_eq!(parent.capability.get_tag(), cap_tag::cap_null_cap);
+        println!("Test revoke_progress_test passed");
+    }
+
+    /// 多个线程在大内核锁下交替对同一条派生链表做插入、移动、交换和删除
+    #[cfg(feature = "host")]
+    #[test_case]
+    pub fn mdb_lock_interleaving_test() {
+        use crate::cte::{cte_insert_locked, cte_move_locked, cte_swap_locked};
+        use crate::deps::KernelHooks;
+        use crate::lock::BigLockGuard;
+        use crate::mdb::validate_mdb;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+        use std::sync::Mutex;
+
+        println!("-----------------------------------");
+        println!("Entering mdb_lock_interleaving_test case");
+        const THREADS: usize = 4;
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let empty = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut slots = vec![empty; 1 + THREADS * 2];
+        slots[0].capability = ep.clone();
+        slots[0].cteMDBNode = mdb_node::new(0, 1, 1, 0);
+        let base = slots.as_mut_ptr() as usize;
+        let slot = |i: usize| convert_to_mut_type_ref::<cte_t>(base + i * size_of::<cte_t>());
+        let kernel_lock = Mutex::new(());
+
+        std::thread::scope(|scope| {
+            for t in 0..THREADS {
+                let (kernel_lock, ep) = (&kernel_lock, &ep);
+                scope.spawn(move || {
+                    let (a, b) = (1 + t * 2, 2 + t * 2);
+                    for _ in 0..200 {
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        cte_insert_locked(&guard, ep, slot(0), slot(a));
+                        drop(guard);
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        cte_move_locked(&guard, ep, slot(a), slot(b));
+                        drop(guard);
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        cte_insert_locked(&guard, ep, slot(0), slot(a));
+                        drop(guard);
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        let (cap_a, cap_b) =
+                            (slot(a).capability.clone(), slot(b).capability.clone());
+                        cte_swap_locked(&guard, &cap_a, slot(a), &cap_b, slot(b));
+                        drop(guard);
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        slot(a).delete_one_with::<KernelHooks, _>(&guard);
+                        drop(guard);
+                        let guard = BigLockGuard(kernel_lock.lock().unwrap());
+                        slot(b).delete_one_with::<KernelHooks, _>(&guard);
+                    }
+                });
+            }
+        });
+
+        assert!(validate_mdb::<8>(slot(0)).is_ok());
+        assert_eq!(slot(0).cteMDBNode.get_mdbNext(), 0);
+        for entry in &slots[1..] {
+            assert_eq!(entry.capability.get_tag(), cap_tag::cap_null_cap);
+        }
+        println!("Test mdb_lock_interleaving_test passed");
+    }
+
+    /// 每个线程只锁住自己的`CNode`，派生链表互不相交时可以并行修改
+    #[cfg(feature = "host")]
+    #[test_case]
+    pub fn mdb_region_lock_test() {
+        use crate::cte::{cte_insert_locked, cte_move_locked};
+        use crate::deps::KernelHooks;
+        use crate::lock::{MdbGuard, RegionGuard};
+        use crate::mdb::validate_mdb;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+        use std::sync::Mutex;
+
+        println!("-----------------------------------");
+        println!("Entering mdb_region_lock_test case");
+        const THREADS: usize = 4;
+        const SLOTS: usize = 8;
+        let empty = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cnodes = vec![vec![empty; SLOTS]; THREADS];
+        for (t, cnode) in cnodes.iter_mut().enumerate() {
+            cnode[0].capability = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000 * (t + 1)).unsplay();
+            cnode[0].cteMDBNode = mdb_node::new(0, 1, 1, 0);
+        }
+        let regions: Vec<_> = cnodes
+            .iter()
+            .map(|cnode| {
+                let base = cnode.as_ptr() as usize;
+                base..base + SLOTS * size_of::<cte_t>()
+            })
+            .collect();
+        let locks: Vec<_> = (0..THREADS).map(|_| Mutex::new(())).collect();
+
+        // 只锁住第0个CNode时不覆盖其他CNode
+        let guard = RegionGuard::new(&regions[..1], locks[0].lock().unwrap());
+        assert!(guard.covers(cnodes[0][SLOTS - 1].get_ptr()));
+        assert!(!guard.covers(cnodes[1][0].get_ptr()));
+        drop(guard);
+
+        std::thread::scope(|scope| {
+            for t in 0..THREADS {
+                let (region, lock) = (&regions[t..t + 1], &locks[t]);
+                let slot = move |i: usize| {
+                    convert_to_mut_type_ref::<cte_t>(region[0].start + i * size_of::<cte_t>())
+                };
+                scope.spawn(move || {
+                    for round in 0..200 {
+                        let guard = RegionGuard::new(region, lock.lock().unwrap());
+                        let src = 1 + round % (SLOTS - 2);
+                        let cap = slot(0).capability.clone();
+                        cte_insert_locked(&guard, &cap, slot(0), slot(src));
+                        cte_move_locked(&guard, &cap, slot(src), slot(SLOTS - 1));
+                        drop(guard);
+                        let guard = RegionGuard::new(region, lock.lock().unwrap());
+                        assert_eq!(
+                            slot(0).revoke_with::<KernelHooks, _>(&guard).slots_deleted,
+                            1
+                        );
+                    }
+                });
+            }
+        });
+
+        for cnode in &cnodes {
+            assert!(validate_mdb::<8>(&cnode[0]).is_ok());
+            assert_eq!(cnode[0].cteMDBNode.get_mdbNext(), 0);
+            assert_eq!(cnode[SLOTS - 1].capability.get_tag(), cap_tag::cap_null_cap);
+        }
+        println!("Test mdb_region_lock_test passed");
+    }
+
+    #[test_case]
+    pub fn validate_mdb_test() {
+        use crate::mdb::{validate_mdb, MdbViolation};
+        use sel4_common::structures_gen::{
+            cap_asid_control_cap, cap_domain_cap, cap_irq_control_cap, cap_null_cap,
+        };
+
+        println!("-----------------------------------");
+        println!("Entering validate_mdb_test case");
+        let cap1 = cap_asid_control_cap::new().unsplay();
+        let cap2 = cap_domain_cap::new().unsplay();
+        let cap3 = cap_irq_control_cap::new().unsplay();
+        let mut cte1 = cte_t {
+            capability: cap3,
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte2 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut cte3 = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        cte_insert(&cap1, &mut cte1, &mut cte2);
+        cte_insert(&cap2, &mut cte2, &mut cte3);
+        assert!(validate_mdb::<4>(&cte2).is_ok());
+
+        cte3.cteMDBNode.set_mdbPrev(&mut cte1 as *mut cte_t as u64);
+        let report = validate_mdb::<4>(&cte1);
+        assert!(report.iter().any(|v| *v
+            == MdbViolation::BrokenNextLink {
+                slot: cte2.get_ptr(),
+                next: cte3.get_ptr(),
+            }));
+        println!("Test validate_mdb_test passed");
+    }
+
+    #[test_case]
+    pub fn validate_mdb_order_test() {
+        use crate::cte::mdb_ptr_func;
+        use crate::mdb::{validate_mdb, validate_mdb_range, MdbViolation};
+        use sel4_common::structures_gen::cap_endpoint_cap;
+
+        /// 按给定顺序把`slots`串成一条链表
+        fn link(slots: &mut [cte_t; 3], order: [usize; 3]) {
+            let ptrs = order.map(|i| slots[i].get_ptr());
+            for (pos, &i) in order.iter().enumerate() {
+                let prev = if pos == 0 { 0 } else { ptrs[pos - 1] };
+                let next = if pos == 2 { 0 } else { ptrs[pos + 1] };
+                slots[i].cteMDBNode.set_prev_ptr(prev);
+                slots[i].cteMDBNode.set_next_ptr(next);
+            }
+        }
+
+        println!("-----------------------------------");
+        println!("Entering validate_mdb_order_test case");
+        // 0是可撤销的原始endpoint，1是它的拷贝，2是无关的endpoint
+        let mut slots: [cte_t; 3] = [
+            cte_t {
+                capability: cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay(),
+                cteMDBNode: mdb_node::new(0, 0, 0, 0),
+            },
+            cte_t {
+                capability: cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay(),
+                cteMDBNode: mdb_node::new(0, 0, 0, 0),
+            },
+            cte_t {
+                capability: cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x2000).unsplay(),
+                cteMDBNode: mdb_node::new(0, 0, 0, 0),
+            },
+        ];
+        slots[0].cteMDBNode.set_mdbRevocable(1);
+        assert!(slots[0].is_mdb_parent_of(&slots[1]));
+
+        link(&mut slots, [0, 1, 2]);
+        assert!(validate_mdb::<4>(&slots[2]).is_ok());
+
+        // 子节点和父节点之间隔着一个无关节点，只检查相邻节点时发现不了
+        link(&mut slots, [1, 2, 0]);
+        let expected = MdbViolation::ChildBeforeParent {
+            parent: slots[0].get_ptr(),
+            child: slots[1].get_ptr(),
+        };
+        let report = validate_mdb::<4>(&slots[2]);
+        assert_eq!(report.count(), 1);
+        assert!(report.iter().any(|v| *v == expected));
+        assert!(validate_mdb_range::<4>(&slots).is_ok());
+        println!("Test validate_mdb_order_test passed");
+    }
+
+    #[test_case]
+    pub fn mdb_iter_test() {
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering mdb_iter_test case");
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let badged = cap_endpoint_cap::new(42, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut orig = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut minted = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut minted_copy = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut copy = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut orig, &ep);
+        cte_insert(&badged, &mut orig, &mut minted);
+        cte_insert(&badged, &mut minted, &mut minted_copy);
+        cte_insert(&ep, &mut orig, &mut copy);
+        // orig -> copy -> minted -> minted_copy
+        assert_eq!(orig.descendants().count(), 3);
+        let mut children = orig.children();
+        assert_eq!(children.next().map(|c| c.get_ptr()), Some(copy.get_ptr()));
+        assert_eq!(children.next().map(|c| c.get_ptr()), Some(minted.get_ptr()));
+        assert!(children.next().is_none());
+        assert_eq!(minted.children().count(), 1);
+        assert_eq!(minted.siblings_same_object().count(), 3);
+        println!("Test mdb_iter_test passed");
+    }
+
+    #[test_case]
+    pub fn dump_cspace_test() {
+        use crate::debug::capdl::dump_cspace;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering dump_cspace_test case");
+        let cnode: [cte_t; 4] = core::array::from_fn(|_| cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        });
+        let root = cap_cnode_cap::new(0, 0, 2, cnode.as_ptr() as u64);
+        let mut parent = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let ep = cap_endpoint_cap::new(7, 0, 0, 1, 1, 0x1000).unsplay();
+        insert_new_cap(
+            &mut parent,
+            convert_to_mut_type_ref(cnode[1].get_ptr()),
+            &ep,
+        );
+        insert_new_cap(
+            &mut parent,
+            convert_to_mut_type_ref(cnode[2].get_ptr()),
+            &root.clone().unsplay(),
+        );
+        let mut buffer = Buffer::new();
+        assert!(dump_cspace(&root, &mut buffer).is_ok());
+        let text = buffer.as_str();
+        println!("{}", text);
+        assert!(text.contains("ep_0x1000 = ep"));
+        assert!(text.contains("0x1: ep_0x1000 (RW, badge: 7)"));
+        assert_eq!(text.matches(" = cnode").count(), 1);
+        println!("Test dump_cspace_test passed");
+    }
+
+    #[test_case]
+    pub fn export_dot_test() {
+        use crate::debug::dot::export_dot;
+        use sel4_common::structures_gen::{cap_endpoint_cap, cap_null_cap};
+
+        println!("-----------------------------------");
+        println!("Entering export_dot_test case");
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let badged = cap_endpoint_cap::new(42, 1, 1, 1, 1, 0x1000).unsplay();
+        let mut root = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut orig = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut minted = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let mut minted_copy = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        insert_new_cap(&mut root, &mut orig, &ep);
+        cte_insert(&badged, &mut orig, &mut minted);
+        cte_insert(&badged, &mut minted, &mut minted_copy);
+        let mut buffer = Buffer::new();
+        assert!(export_dot(&orig, &mut buffer).is_ok());
+        let text = buffer.as_str();
+        println!("{}", text);
+        let mut edge = Buffer::new();
+        write!(
+            edge,
+            "n{:#x} -> n{:#x};",
+            minted.get_ptr(),
+            minted_copy.get_ptr()
+        )
+        .unwrap();
+        assert!(text.contains(edge.as_str()));
+        assert!(text.contains("badge: 42"));
+        assert_eq!(text.matches(" -> ").count(), 2);
+        println!("Test export_dot_test passed");
+    }
+
+    #[test_case]
+    pub fn unknown_arch_cap_test() {
+        use crate::arch::{arch_same_region_as, set_unknown_arch_cap_hook};
+        use sel4_common::structures_gen::cap_endpoint_cap;
+
+        static REPORTED: AtomicUsize = AtomicUsize::new(0);
+        fn on_unknown(_site: &'static str, _tag: u64) {
+            REPORTED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        println!("-----------------------------------");
+        println!("Entering unknown_arch_cap_test case");
+        set_unknown_arch_cap_hook(Some(on_unknown));
+        let ep = cap_endpoint_cap::new(0, 1, 1, 1, 1, 0x1000).unsplay();
+        let slot = cte_t {
+            capability: ep.clone(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+        let ret = slot.arch_derive_cap(&ep);
+        assert_eq!(ret.status, exception_t::EXCEPTION_SYSCALL_ERROR);
+        assert_eq!(ret.capability.get_tag(), cap_tag::cap_null_cap);
+        assert!(!arch_same_region_as(&ep, &ep));
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 2);
+        set_unknown_arch_cap_hook(None);
+        println!("Test unknown_arch_cap_test passed");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test_case]
+    pub fn x86_64_arch_cap_test() {
+        use crate::arch::{arch_mask_cap_rights, arch_same_object_as, arch_same_region_as};
+        use sel4_common::shared_types_bf_gen::seL4_CapRights;
+        use sel4_common::structures_gen::{
+            cap_frame_cap, cap_io_port_cap, cap_io_port_control_cap, cap_null_cap,
+        };
+
+        // x86_64 的`frame_cap`：ASID、基址、大小、映射类型、映射地址、权限、是否设备内存
+        fn frame(base: usize, size: u64) -> cap {
+            let capability = cap_frame_cap::new(0, 0, 0, 0, 0, 0, 0).unsplay();
+            cap::cap_frame_cap(&capability).set_capFBasePtr(base as u64);
+            cap::cap_frame_cap(&capability).set_capFSize(size);
+            // VMReadWrite
+            cap::cap_frame_cap(&capability).set_capFVMRights(3);
+            capability
+        }
+
+        println!("-----------------------------------");
+        println!("Entering x86_64_arch_cap_test case");
+        let slot = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: mdb_node::new(0, 0, 0, 0),
+        };
+
+        // IO port control不能派生，派生结果为空cap但不报错
+        let control = cap_io_port_control_cap::new().unsplay();
+        let ret = slot.arch_derive_cap(&control);
+        assert_eq!(ret.status, exception_t::EXCEPTION_NONE);
+        assert_eq!(ret.capability.get_tag(), cap_tag::cap_null_cap);
+        let port = cap_io_port_cap::new(0x3f8, 0x3ff).unsplay();
+        let ret = slot.arch_derive_cap(&port);
+        assert_eq!(ret.status, exception_t::EXCEPTION_NONE);
+        assert_eq!(ret.capability.get_tag(), cap_tag::cap_io_port_cap);
+
+        // 派生出的frame_cap不再处于映射状态
+        let mapped = frame(0x20_0000, 0);
+        cap::cap_frame_cap(&mapped).set_capFMappedASID(5);
+        cap::cap_frame_cap(&mapped).set_capFMappedAddress(0x40_0000);
+        cap::cap_frame_cap(&mapped).set_capFMapType(1);
+        let ret = slot.arch_derive_cap(&mapped);
+        assert_eq!(ret.status, exception_t::EXCEPTION_NONE);
+        let derived = cap::cap_frame_cap(&ret.capability);
+        assert_eq!(derived.get_capFMappedASID(), 0);
+        assert_eq!(derived.get_capFMappedAddress(), 0);
+        assert_eq!(derived.get_capFMapType(), 0);
+        assert_eq!(derived.get_capFBasePtr(), 0x20_0000);
+        // 原cap保持不变
+        assert_eq!(cap::cap_frame_cap(&mapped).get_capFMappedASID(), 5);
+
+        // 2M的大页包含其中的4K页，反过来不成立
+        let large = frame(0x20_0000, 1);
+        let small = frame(0x20_1000, 0);
+        assert!(arch_same_region_as(&large, &small));
+        assert!(!arch_same_region_as(&small, &large));
+        assert!(!arch_same_region_as(&small, &frame(0x20_2000, 0)));
+        assert!(arch_same_region_as(&small, &frame(0x20_1000, 0)));
+        assert!(!arch_same_object_as(&large, &small));
+        // IO port control覆盖所有IO port，但与它们不是同一个对象
+        assert!(arch_same_region_as(&control, &port));
+        assert!(!arch_same_region_as(&port, &control));
+        assert!(!arch_same_object_as(&control, &port));
+        assert!(arch_same_region_as(
+            &port,
+            &cap_io_port_cap::new(0x3f8, 0x3ff).unsplay()
+        ));
+        assert!(!arch_same_region_as(
+            &port,
+            &cap_io_port_cap::new(0x3f8, 0x3fa).unsplay()
+        ));
+
+        // 只读权限把VMReadWrite降为VMReadOnly，非frame_cap不受影响
+        let read_only = seL4_CapRights::new(0, 0, 1, 0);
+        let masked = arch_mask_cap_rights(read_only, &small);
+        assert_eq!(cap::cap_frame_cap(&masked).get_capFVMRights(), 2);
+        assert_eq!(cap::cap_frame_cap(&small).get_capFVMRights(), 3);
+        let all = seL4_CapRights::new(1, 1, 1, 1);
+        let masked = arch_mask_cap_rights(all, &small);
+        assert_eq!(cap::cap_frame_cap(&masked).get_capFVMRights(), 3);
+        let masked = arch_mask_cap_rights(read_only, &port);
+        assert_eq!(masked.get_tag(), cap_tag::cap_io_port_cap);
+        println!("Test x86_64_arch_cap_test passed");
+    }
+
+    #[cfg(target_arch = "loongarch64")]
+    #[test_case]
+    pub fn loongarch64_arch_test() {
+        use crate::arch::{arch_same_region_as, decode_mdb_ptr, encode_mdb_ptr, VA_BITS};
+        use crate::cte::mdb_ptr_func;
+        use sel4_common::structures_gen::{cap_frame_cap, cap_null_cap};
+
+        fn frame(base: usize, size: u64) -> cap {
+            let capability = cap_frame_cap::new(0, 0, 0, 0, 0, 0).unsplay();
+            cap::cap_frame_cap(&capability).set_capFBasePtr(base as u64);
+            cap::cap_frame_cap(&capability).set_capFSize(size);
+            capability
+        }
+
+        println!("-----------------------------------");
+        println!("Entering loongarch64_arch_test case");
+        assert_eq!(VA_BITS, 48);
+        // bit 47为1的地址解码时高位补1，低半部分的地址保持不变
+        let high = 0xffff_8000_0010_2040usize;
+        assert_eq!(encode_mdb_ptr(high), 0x8000_0010_2040);
+        assert_eq!(decode_mdb_ptr(0x8000_0010_2040), high);
+        assert_eq!(decode_mdb_ptr(0x8000_0010_2040 | 0b11), high);
+        assert_eq!(decode_mdb_ptr(0x7fff_0010_2040), 0x7fff_0010_2040);
+        assert_eq!(decode_mdb_ptr(0), 0);
+
+        // 经过mdb_node读写后指针和标志位都保持不变
+        let mut node = mdb_node::new(0, 1, 1, 0);
+        node.set_next_ptr(high);
+        node.set_prev_ptr(high);
+        assert_eq!(node.get_next_ptr(), high);
+        assert_eq!(node.get_prev_ptr(), high);
+        assert_eq!(node.get_mdbRevocable(), 1);
+        assert_eq!(node.get_mdbFirstBadged(), 1);
+        let slot = cte_t {
+            capability: cap_null_cap::new().unsplay(),
+            cteMDBNode: node,
+        };
+        assert_eq!(slot.get_volatile_value(), high);
+
+        // 派生出的frame_cap不再处于映射状态
+        let mapped = frame(0x20_0000, 0);
+        cap::cap_frame_cap(&mapped).set_capFMappedASID(5);
+        cap::cap_frame_cap(&mapped).set_capFMappedAddress(0x40_0000);
+        let ret = slot.arch_derive_cap(&mapped);
+        assert_eq!(ret.status, exception_t::EXCEPTION_NONE);
+        assert_eq!(cap::cap_frame_cap(&ret.capability).get_capFMappedASID(), 0);
+        assert_eq!(
+            cap::cap_frame_cap(&ret.capability).get_capFMappedAddress(),
+            0
+        );
+        assert_eq!(
+            cap::cap_frame_cap(&ret.capability).get_capFBasePtr(),
+            0x20_0000
+        );
+        // 未映射的页表不能派生
+        let pt = cap_page_table_cap::new(0, 0, 0, 0).unsplay();
+        let ret = slot.arch_derive_cap(&pt);
+        assert_eq!(ret.status, exception_t::EXCEPTION_SYSCALL_ERROR);
+        assert_eq!(ret.capability.get_tag(), cap_tag::cap_null_cap);
+        let asid_control = cap_asid_control_cap::new().unsplay();
+        assert_eq!(
+            slot.arch_derive_cap(&asid_control).capability.get_tag(),
+            cap_tag::cap_asid_control_cap
+        );
+
+        // 大页包含其中的小页，反过来不成立
+        let large = frame(0x20_0000, 1);
+        let small = frame(0x20_1000, 0);
+        assert!(arch_same_region_as(&large, &small));
+        assert!(!arch_same_region_as(&small, &large));
+        assert!(!arch_same_region_as(&small, &frame(0x20_2000, 0)));
+        assert!(arch_same_region_as(&asid_control, &asid_control));
+        assert!(!arch_same_region_as(&asid_control, &small));
+        println!("Test loongarch64_arch_test passed");
+    }
+
+    #[cfg(not(feature = "host"))]
+    #[test_case]
+    pub fn shutdown_test() {
+        println!("All Test Cases passed, shutdown");
+        shutdown();
+    }
+
+    /// 不依赖堆内存的`fmt::Write`实现，用于检查导出的文本
+    struct Buffer {
+        data: [u8; 1024],
+        len: usize,
+    }
+
+    impl Buffer {
+        fn new() -> Self {
+            Buffer {
+                data: [0; 1024],
+                len: 0,
+            }
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.data[..self.len]).unwrap()
+        }
+    }
+
+    impl core::fmt::Write for Buffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(core::fmt::Error);
+            }
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    fn new_mock_slot(tag: u64) -> cte_t {
+        match tag {
+            cap_tag::cap_cnode_cap => {
+                let capability = cap_cnode_cap::new(0, 0, 0, 0);
+                cte_t {
+                    capability: capability.unsplay(),
+                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
+                }
+            }
+            // x86_64 的`frame_cap`多一个`capFMapType`字段
+            #[cfg(any(target_arch = "riscv64", target_arch = "riscv32"))]
+            cap_tag::cap_frame_cap => {
+                let capability = cap_frame_cap::new(0, 0, 0, 0, 0, 0);
+                cte_t {
+                    capability: capability.unsplay(),
+                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
+                }
+            }
+            cap_tag::cap_page_table_cap => {
+                let capability = cap_page_table_cap::new(0, 0, 0, 0);
+                cte_t {
+                    capability: capability.unsplay(),
+                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
+                }
+            }
+            cap_tag::cap_asid_control_cap => {
+                let capability = cap_asid_control_cap::new();
+                cte_t {
+                    capability: capability.unsplay(),
+                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
+                }
+            }
+            cap_tag::cap_asid_pool_cap => {
+                let capability = cap_asid_pool_cap::new(0, 0);
+                cte_t {
+                    capability: capability.unsplay(),
+                    cteMDBNode: mdb_node::new(0, 0, 0, 0),
+                }
+            }
+            _ => panic!("Invalid cap tag"),
+        }
+    }
+
+    #[cfg(not(feature = "host"))]
+    #[panic_handler]
+    fn panic(info: &core::panic::PanicInfo) -> ! {
+        println!("{}", info);
+        shutdown()
+    }
+
+    pub fn test_runner(tests: &[&dyn Fn()]) {
+        println!("Running {} tests", tests.len());
+        for test in tests {
+            test();
+        }
+    }
+
+    #[cfg(not(feature = "host"))]
+    #[no_mangle]
+    pub fn call_test_main() {
+        extern "C" {
+            fn trap_entry();
+        }
+        unsafe {
+            stvec::write(trap_entry as usize, TrapMode::Direct);
+        }
+        crate::test_main();
+    }
+    #[cfg(not(feature = "host"))]
+    #[no_mangle]
+    pub fn c_handle_syscall() {
+        unsafe {
+            core::arch::asm!("sret");
+        }
+    }
+
+    static DELETED: AtomicUsize = AtomicUsize::new(0);
+    static CLEARED: AtomicUsize = AtomicUsize::new(0);
+    static PREEMPT: AtomicBool = AtomicBool::new(false);
+
+    /// 测试共用的`CSpaceHooks`和`UntypedHooks`，使用前先调用`reset`
+    ///
+    /// 所有`cap`都视为可以直接删除，`post_cap_deletion`的次数和`clear_memory`清零的字节数分别计入
+    /// `deleted`和`cleared`。`preempt_next`之后的下一次`preemption_point`返回`EXCEPTION_PREEMTED`。
+    /// `create_object`返回指向对象地址的`endpoint_cap`。
+    struct TestHooks;
+
+    impl TestHooks {
+        fn reset() {
+            DELETED.store(0, Ordering::Relaxed);
+            CLEARED.store(0, Ordering::Relaxed);
+            PREEMPT.store(false, Ordering::Relaxed);
+        }
+
+        fn preempt_next() {
+            PREEMPT.store(true, Ordering::Relaxed);
+        }
+
+        fn deleted() -> usize {
+            DELETED.load(Ordering::Relaxed)
+        }
+
+        fn cleared() -> usize {
+            CLEARED.load(Ordering::Relaxed)
+        }
+    }
+
+    impl CSpaceHooks for TestHooks {
+        fn finalise_cap(_capability: &cap, _final: bool, _exposed: bool) -> FinaliseCapRet {
+            FinaliseCapRet {
+                remainder: sel4_common::structures_gen::cap_null_cap::new().unsplay(),
+                cleanupInfo: sel4_common::structures_gen::cap_null_cap::new().unsplay(),
+            }
+        }
+        fn post_cap_deletion(_capability: &cap) {
+            DELETED.fetch_add(1, Ordering::Relaxed);
+        }
+        fn preemption_point() -> exception_t {
+            if PREEMPT.swap(false, Ordering::Relaxed) {
+                exception_t::EXCEPTION_PREEMTED
+            } else {
+                exception_t::EXCEPTION_NONE
+            }
+        }
+        fn cancel_badged_sends(_ep_ptr: usize, _badge: u64) {}
+    }
+
+    impl UntypedHooks for TestHooks {
+        fn object_size(_obj_type: usize, _user_size: usize) -> Option<usize> {
+            None
+        }
+        fn is_frame_type(_obj_type: usize) -> bool {
+            false
+        }
+        fn create_object(
+            _obj_type: usize,
+            region_base: usize,
+            _user_size: usize,
+            _device: bool,
+        ) -> cap {
+            sel4_common::structures_gen::cap_endpoint_cap::new(0, 1, 1, 1, 1, region_base as u64)
+                .unsplay()
+        }
+        fn clear_memory(_base: usize, size_bits: usize) {
+            CLEARED.fetch_add(1 << size_bits, Ordering::Relaxed);
+        }
+    }
+
+    /// 测试中`deps`外部接口的模拟实现，所有`cap`都视为可以直接删除
+    mod mock {
+        use crate::structures::FinaliseCapRet;
+        use sel4_common::{
+            structures::exception_t,
+            structures_gen::{cap, cap_null_cap},
+        };
+
+        #[no_mangle]
+        pub extern "C" fn finalise_cap(
+            _capability: &cap,
+            _final: bool,
+            _exposed: bool,
+        ) -> FinaliseCapRet {
+            FinaliseCapRet {
+                remainder: cap_null_cap::new().unsplay(),
+                cleanupInfo: cap_null_cap::new().unsplay(),
+            }
+        }
+
+        #[no_mangle]
+        pub extern "C" fn post_cap_deletion(_capability: &cap) {}
+
+        #[no_mangle]
+        pub extern "C" fn preemption_point() -> exception_t {
+            exception_t::EXCEPTION_NONE
+        }
+
+        #[no_mangle]
+        pub extern "C" fn cancel_badged_sends(_ep_ptr: usize, _badge: u64) {}
+
+        #[cfg(feature = "lookup_cache")]
+        #[no_mangle]
+        pub extern "C" fn get_current_cpu_index() -> usize {
+            0
         }
     }
 }
@@ -0,0 +1,91 @@
+//! 多核下修改能力派生树时的加锁约定
+//!
+//! cspace本身不持有锁，修改`mdb_node`的操作（`cte_*_locked`以及`delete_all_with`、`delete_one_with`、`revoke_with`）
+//! 由调用者传入已经持有的锁的guard。
+//! 调试版本中，这些操作在写入之前检查所有会被修改的`slot`，包括派生树中前后相邻的节点，都在guard的保护范围内，
+//! 不满足时直接panic，而不是在没有同步的情况下改写其他核可能正在修改的链表。
+//!
+//! - 大内核锁：用`BigLockGuard`包装锁本身的guard，保护所有`slot`
+//! - 细粒度锁：调用者锁住若干内核对象（例如`CNode`）后，用它们的地址范围构造`RegionGuard`
+//! - 单核：`NoLock`，不带`_locked`后缀和`_with`后缀的接口都使用它
+//!
+//! 派生树中相邻的节点可能位于其他`CNode`中，细粒度锁需要调用者先读出相邻节点并一起加锁：
+//!
+//! ```ignore
+//! let _cnode = cnode_lock.lock();
+//! let regions = [cnode_base..cnode_base + cnode_size];
+//! let guard = RegionGuard::new(&regions, _cnode);
+//! cte_move_locked(&guard, &new_cap, src_slot, dest_slot);
+//! ```
+use core::ops::Range;
+
+use crate::cte::{cte_t, mdb_ptr_func};
+
+/// 修改派生树时持有的锁
+pub trait MdbGuard {
+    /// `slot`的`cap`和`mdb_node`是否受当前guard保护
+    fn covers(&self, slot: usize) -> bool;
+}
+
+/// 单核或已经通过其他方式保证互斥时使用，保护所有`slot`
+pub struct NoLock;
+
+impl MdbGuard for NoLock {
+    #[inline]
+    fn covers(&self, _slot: usize) -> bool {
+        true
+    }
+}
+
+/// 大内核锁，`G`是锁本身的guard，在`BigLockGuard`drop时一起释放
+pub struct BigLockGuard<G>(pub G);
+
+impl<G> MdbGuard for BigLockGuard<G> {
+    #[inline]
+    fn covers(&self, _slot: usize) -> bool {
+        true
+    }
+}
+
+/// 细粒度锁，`regions`是已经加锁的内核对象的地址范围，`G`是这些锁的guard
+pub struct RegionGuard<'a, G> {
+    regions: &'a [Range<usize>],
+    _guards: G,
+}
+
+impl<'a, G> RegionGuard<'a, G> {
+    pub fn new(regions: &'a [Range<usize>], guards: G) -> Self {
+        RegionGuard {
+            regions,
+            _guards: guards,
+        }
+    }
+}
+
+impl<G> MdbGuard for RegionGuard<'_, G> {
+    #[inline]
+    fn covers(&self, slot: usize) -> bool {
+        self.regions.iter().any(|region| region.contains(&slot))
+    }
+}
+
+/// 断言`slots`中的非空指针都受`guard`保护，只在调试版本中检查
+#[inline]
+pub(crate) fn assert_locked<G: MdbGuard>(guard: &G, slots: &[usize]) {
+    for &slot in slots {
+        debug_assert!(
+            slot == 0 || guard.covers(slot),
+            "slot {:#x} is not covered by the mdb lock",
+            slot
+        );
+    }
+}
+
+/// `slot`在派生树中的前后节点
+#[inline]
+pub(crate) fn neighbours(slot: &cte_t) -> [usize; 2] {
+    [
+        slot.cteMDBNode.get_prev_ptr(),
+        slot.cteMDBNode.get_next_ptr(),
+    ]
+}
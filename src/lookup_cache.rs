@@ -0,0 +1,116 @@
+//! `resolve_address_bits`的每核翻译缓存
+//!
+//! 缓存以（根`CNode`、`cap_ptr`、depth）为键，记录解析得到的`slot`，只缓存解析成功的结果。
+//! 解析结果只依赖路径上各级`CNode cap`，所以任何存放`CNode cap`的`slot`发生移动、交换、清空或覆盖时，
+//! 都通过`invalidate`递增全局的代数使所有核上的缓存同时失效。
+//!
+//! 查找时在遍历`CNode`之前读取代数并随结果一起保存，遍历期间发生的修改会使这次写入的结果直接失效。
+use crate::cte::{cte_t, resolve_address_bits_uncached};
+use crate::deps::get_current_cpu_index;
+use crate::structures::resolveAddressBits_ret_t;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use sel4_common::sel4_config::CONFIG_MAX_NUM_NODES;
+use sel4_common::structures::exception_t;
+use sel4_common::structures_gen::{cap, cap_tag};
+
+/// 每个核上的缓存项数，必须是2的幂
+pub const LOOKUP_CACHE_ENTRIES: usize = 64;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    generation: usize,
+    root: usize,
+    root_guard: usize,
+    root_guard_size: usize,
+    root_radix: usize,
+    cap_ptr: usize,
+    n_bits: usize,
+    slot: *mut cte_t,
+    bits_remaining: usize,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        generation: 0,
+        root: 0,
+        root_guard: 0,
+        root_guard_size: 0,
+        root_radix: 0,
+        cap_ptr: 0,
+        n_bits: 0,
+        slot: core::ptr::null_mut(),
+        bits_remaining: 0,
+    };
+}
+
+struct LookupCache(UnsafeCell<[[Entry; LOOKUP_CACHE_ENTRIES]; CONFIG_MAX_NUM_NODES]>);
+
+// 每个核只访问自己的那一行
+unsafe impl Sync for LookupCache {}
+
+static CACHE: LookupCache = LookupCache(UnsafeCell::new(
+    [[Entry::EMPTY; LOOKUP_CACHE_ENTRIES]; CONFIG_MAX_NUM_NODES],
+));
+
+/// 从1开始，保证全零的缓存项无效
+static GENERATION: AtomicUsize = AtomicUsize::new(1);
+
+/// 使所有核上的缓存失效
+#[inline]
+pub fn invalidate() {
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+#[inline]
+fn index(root: usize, cap_ptr: usize, n_bits: usize) -> usize {
+    ((root >> 5) ^ cap_ptr ^ (n_bits << 3)) & (LOOKUP_CACHE_ENTRIES - 1)
+}
+
+/// 带缓存的`resolve_address_bits`
+pub(crate) fn resolve(node_cap: &cap, cap_ptr: usize, n_bits: usize) -> resolveAddressBits_ret_t {
+    if node_cap.get_tag() != cap_tag::cap_cnode_cap {
+        return resolve_address_bits_uncached(node_cap, cap_ptr, n_bits);
+    }
+    let cnode = cap::cap_cnode_cap(node_cap);
+    let root = cnode.get_capCNodePtr() as usize;
+    let root_guard = cnode.get_capCNodeGuard() as usize;
+    let root_guard_size = cnode.get_capCNodeGuardSize() as usize;
+    let root_radix = cnode.get_capCNodeRadix() as usize;
+    let cpu = unsafe { get_current_cpu_index() };
+    debug_assert!(cpu < CONFIG_MAX_NUM_NODES, "invalid cpu index {}", cpu);
+    if cpu >= CONFIG_MAX_NUM_NODES {
+        return resolve_address_bits_uncached(node_cap, cap_ptr, n_bits);
+    }
+    let generation = GENERATION.load(Ordering::Acquire);
+    let entry = unsafe { &mut (*CACHE.0.get())[cpu][index(root, cap_ptr, n_bits)] };
+    if entry.generation == generation
+        && entry.root == root
+        && entry.root_guard == root_guard
+        && entry.root_guard_size == root_guard_size
+        && entry.root_radix == root_radix
+        && entry.cap_ptr == cap_ptr
+        && entry.n_bits == n_bits
+    {
+        return resolveAddressBits_ret_t {
+            slot: entry.slot,
+            bitsRemaining: entry.bits_remaining,
+            ..Default::default()
+        };
+    }
+    let ret = resolve_address_bits_uncached(node_cap, cap_ptr, n_bits);
+    if ret.status == exception_t::EXCEPTION_NONE {
+        *entry = Entry {
+            generation,
+            root,
+            root_guard,
+            root_guard_size,
+            root_radix,
+            cap_ptr,
+            n_bits,
+            slot: ret.slot,
+            bits_remaining: ret.bitsRemaining,
+        };
+    }
+    ret
+}
@@ -0,0 +1,365 @@
+//! 能力派生树（`mdb_node`双向链表）的遍历与一致性检查
+//!
+//! `children`、`descendants`和`siblings_same_object`提供了不直接操作裸指针的遍历方式。
+//!
+//! `validate_mdb`用于在调试版本中每次`CNode`操作之后检查派生树是否被破坏，例如：
+//!
+//! ```ignore
+//! #[cfg(debug_assertions)]
+//! {
+//!     let report = validate_mdb::<8>(slot);
+//!     assert!(report.is_ok(), "{:?}", report);
+//! }
+//! ```
+use crate::capability::cap_arch_func;
+use crate::capability::cap_func;
+use crate::capability::same_object_as;
+use crate::cte::{cte_t, mdb_ptr_func};
+use sel4_common::sel4_config::SEL4_MIN_UNTYPED_BITS;
+use sel4_common::structures_gen::{cap, cap_tag};
+use sel4_common::utils::convert_to_type_ref;
+
+impl cte_t {
+    /// 当前`cte`直接派生出的子节点
+    pub fn children(&self) -> MdbChildren<'_> {
+        MdbChildren {
+            descendants: self.descendants(),
+            current: None,
+        }
+    }
+
+    /// 当前`cte`派生出的所有节点，即链表中紧跟在当前节点之后、`is_mdb_parent_of`成立的连续一段
+    pub fn descendants(&self) -> MdbDescendants<'_> {
+        MdbDescendants {
+            parent: self,
+            next: self.cteMDBNode.get_next_ptr(),
+        }
+    }
+
+    /// 与当前`cte`相邻且指向同一内核对象的其他节点，先向前再向后遍历
+    pub fn siblings_same_object(&self) -> MdbSameObject<'_> {
+        MdbSameObject {
+            origin: self,
+            prev: self.cteMDBNode.get_prev_ptr(),
+            next: self.cteMDBNode.get_next_ptr(),
+        }
+    }
+}
+
+/// 见[`cte_t::descendants`]
+pub struct MdbDescendants<'a> {
+    parent: &'a cte_t,
+    next: usize,
+}
+
+impl<'a> Iterator for MdbDescendants<'a> {
+    type Item = &'a cte_t;
+
+    fn next(&mut self) -> Option<&'a cte_t> {
+        if self.next == 0 {
+            return None;
+        }
+        let slot = convert_to_type_ref::<cte_t>(self.next);
+        if !self.parent.is_mdb_parent_of(slot) {
+            self.next = 0;
+            return None;
+        }
+        self.next = slot.cteMDBNode.get_next_ptr();
+        Some(slot)
+    }
+}
+
+/// 见[`cte_t::children`]
+///
+/// 子节点的后代在链表中紧跟在子节点之后，所以只需要跳过被上一个子节点派生出的节点
+pub struct MdbChildren<'a> {
+    descendants: MdbDescendants<'a>,
+    current: Option<&'a cte_t>,
+}
+
+impl<'a> Iterator for MdbChildren<'a> {
+    type Item = &'a cte_t;
+
+    fn next(&mut self) -> Option<&'a cte_t> {
+        loop {
+            let slot = self.descendants.next()?;
+            if let Some(child) = self.current {
+                if child.is_mdb_parent_of(slot) {
+                    continue;
+                }
+            }
+            self.current = Some(slot);
+            return Some(slot);
+        }
+    }
+}
+
+/// 见[`cte_t::siblings_same_object`]
+pub struct MdbSameObject<'a> {
+    origin: &'a cte_t,
+    prev: usize,
+    next: usize,
+}
+
+impl<'a> Iterator for MdbSameObject<'a> {
+    type Item = &'a cte_t;
+
+    fn next(&mut self) -> Option<&'a cte_t> {
+        if self.prev != 0 {
+            let slot = convert_to_type_ref::<cte_t>(self.prev);
+            if same_object_as(&slot.capability, &self.origin.capability) {
+                self.prev = slot.cteMDBNode.get_prev_ptr();
+                return Some(slot);
+            }
+            self.prev = 0;
+        }
+        if self.next != 0 {
+            let slot = convert_to_type_ref::<cte_t>(self.next);
+            if same_object_as(&self.origin.capability, &slot.capability) {
+                self.next = slot.cteMDBNode.get_next_ptr();
+                return Some(slot);
+            }
+            self.next = 0;
+        }
+        None
+    }
+}
+
+/// 派生树中发现的不一致，字段均为`slot`的地址
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MdbViolation {
+    /// `slot`的`mdbNext`指向的节点，其`mdbPrev`不指向`slot`
+    BrokenNextLink { slot: usize, next: usize },
+    /// `slot`的`mdbPrev`指向的节点，其`mdbNext`不指向`slot`
+    BrokenPrevLink { slot: usize, prev: usize },
+    /// `null_cap`所在的`slot`仍然挂在派生树上
+    NullCapInTree { slot: usize },
+    /// 子节点出现在了父节点之前
+    ChildBeforeParent { parent: usize, child: usize },
+    /// 可撤销的带`badge`的`cap`没有设置`mdbFirstBadged`
+    FirstBadgedMissing { slot: usize },
+    /// `untyped_cap`的子节点超出了`capFreeIndex`记录的已分配范围
+    UntypedChildBeyondFreeIndex { untyped: usize, child: usize },
+    /// 沿着`mdbNext`或`mdbPrev`遍历时出现了环
+    Cycle { slot: usize },
+}
+
+/// 检查结果，保存前`N`个不一致，`count`为发现的总数
+#[derive(Clone, Copy, Debug)]
+pub struct MdbReport<const N: usize> {
+    violations: [Option<MdbViolation>; N],
+    count: usize,
+}
+
+impl<const N: usize> Default for MdbReport<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MdbReport<N> {
+    pub fn new() -> Self {
+        MdbReport {
+            violations: [None; N],
+            count: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.count == 0
+    }
+
+    /// 发现的不一致总数，可能大于`N`
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MdbViolation> {
+        self.violations.iter().flatten()
+    }
+
+    fn record(&mut self, violation: MdbViolation) {
+        if self.count < N {
+            self.violations[self.count] = Some(violation);
+        }
+        self.count += 1;
+    }
+}
+
+/// 从`start`所在的派生树链表头开始，检查整条链表
+///
+/// 每个节点都要和它之后的所有节点比较父子关系，复杂度为链表长度的平方，只适合调试时使用
+pub fn validate_mdb<const N: usize>(start: &cte_t) -> MdbReport<N> {
+    let mut report = MdbReport::new();
+    let mut head = start.get_ptr();
+    if walk(start.get_ptr(), mdb_prev, |slot| {
+        head = slot.get_ptr();
+        true
+    }) {
+        report.record(MdbViolation::Cycle {
+            slot: start.get_ptr(),
+        });
+        head = start.get_ptr();
+    }
+    if walk(head, mdb_next, |slot| {
+        check_slot(slot, &mut report);
+        check_parent_order(slot, true, &mut report);
+        true
+    }) {
+        report.record(MdbViolation::Cycle { slot: head });
+    }
+    report
+}
+
+/// 检查一段`CNode`中的所有`slot`与其相邻节点的关系，不遍历整条链表
+pub fn validate_mdb_range<const N: usize>(slots: &[cte_t]) -> MdbReport<N> {
+    let mut report = MdbReport::new();
+    for slot in slots {
+        if slot.capability.get_tag() == cap_tag::cap_null_cap {
+            if slot.cteMDBNode.get_next_ptr() != 0 || slot.cteMDBNode.get_prev_ptr() != 0 {
+                report.record(MdbViolation::NullCapInTree {
+                    slot: slot.get_ptr(),
+                });
+            }
+            continue;
+        }
+        check_slot(slot, &mut report);
+        check_parent_order(slot, false, &mut report);
+    }
+    report
+}
+
+fn check_slot<const N: usize>(slot: &cte_t, report: &mut MdbReport<N>) {
+    let mdb = &slot.cteMDBNode;
+    let ptr = slot.get_ptr();
+    if slot.capability.get_tag() == cap_tag::cap_null_cap {
+        report.record(MdbViolation::NullCapInTree { slot: ptr });
+    }
+    let prev = mdb.get_prev_ptr();
+    if prev != 0 && convert_to_type_ref::<cte_t>(prev).cteMDBNode.get_next_ptr() != ptr {
+        report.record(MdbViolation::BrokenPrevLink { slot: ptr, prev });
+    }
+    let next = mdb.get_next_ptr();
+    if next != 0 {
+        let next_slot = convert_to_type_ref::<cte_t>(next);
+        if next_slot.cteMDBNode.get_prev_ptr() != ptr {
+            report.record(MdbViolation::BrokenNextLink { slot: ptr, next });
+        }
+    }
+    if mdb.get_mdbRevocable() != 0
+        && badge_of(&slot.capability) != 0
+        && mdb.get_mdbFirstBadged() == 0
+    {
+        report.record(MdbViolation::FirstBadgedMissing { slot: ptr });
+    }
+    if slot.capability.get_tag() == cap_tag::cap_untyped_cap {
+        check_untyped_children(slot, report);
+    }
+}
+
+/// 父节点必须出现在子节点之前：`slot`之后的节点如果是`slot`的父节点，记录`ChildBeforeParent`。
+///
+/// `whole_chain`为`false`时只检查紧跟在`slot`之后的节点
+fn check_parent_order<const N: usize>(slot: &cte_t, whole_chain: bool, report: &mut MdbReport<N>) {
+    let next = slot.cteMDBNode.get_next_ptr();
+    if next == 0 {
+        return;
+    }
+    walk(next, mdb_next, |later| {
+        if later.get_ptr() == slot.get_ptr() {
+            return false;
+        }
+        if later.capability.get_tag() != cap_tag::cap_null_cap
+            && later.is_mdb_parent_of(slot)
+            && !slot.is_mdb_parent_of(later)
+        {
+            report.record(MdbViolation::ChildBeforeParent {
+                parent: later.get_ptr(),
+                child: slot.get_ptr(),
+            });
+            return false;
+        }
+        whole_chain
+    });
+}
+
+/// `untyped_cap`派生出的对象必须位于`capFreeIndex`之前
+fn check_untyped_children<const N: usize>(untyped: &cte_t, report: &mut MdbReport<N>) {
+    let ut = cap::cap_untyped_cap(&untyped.capability);
+    let base = ut.get_capPtr() as usize;
+    let free_top = base + ((ut.get_capFreeIndex() as usize) << SEL4_MIN_UNTYPED_BITS);
+    let next = untyped.cteMDBNode.get_next_ptr();
+    if next == 0 {
+        return;
+    }
+    walk(next, mdb_next, |child| {
+        if child.get_ptr() == untyped.get_ptr() || !untyped.is_mdb_parent_of(child) {
+            return false;
+        }
+        let child_cap = &child.capability;
+        if child_cap.get_cap_is_physical() {
+            let child_base = child_cap.get_cap_ptr();
+            let child_top = child_base + mask_bits!(child_cap.get_cap_size_bits());
+            if child_base < base || child_top >= free_top {
+                report.record(MdbViolation::UntypedChildBeyondFreeIndex {
+                    untyped: untyped.get_ptr(),
+                    child: child.get_ptr(),
+                });
+            }
+        }
+        true
+    });
+}
+
+/// endpoint、notification和smc`cap`的`badge`，其他`cap`返回0
+pub(crate) fn badge_of(capability: &cap) -> u64 {
+    match capability.get_tag() {
+        cap_tag::cap_endpoint_cap => cap::cap_endpoint_cap(capability).get_capEPBadge(),
+        cap_tag::cap_notification_cap => cap::cap_notification_cap(capability).get_capNtfnBadge(),
+        #[cfg(feature = "enable_smc")]
+        cap_tag::cap_smc_cap => cap::cap_smc_cap(capability).get_capSMCBadge(),
+        _ => 0,
+    }
+}
+
+#[inline]
+fn mdb_next(slot: &cte_t) -> usize {
+    slot.cteMDBNode.get_next_ptr()
+}
+
+#[inline]
+fn mdb_prev(slot: &cte_t) -> usize {
+    slot.cteMDBNode.get_prev_ptr()
+}
+
+/// 从`start`开始沿`step`遍历，`visit`返回`false`时停止。
+///
+/// 使用Brent算法检测环，出现环时返回`true`。
+fn walk(start: usize, step: fn(&cte_t) -> usize, mut visit: impl FnMut(&cte_t) -> bool) -> bool {
+    let mut tortoise = start;
+    let mut power = 1usize;
+    let mut lam = 0usize;
+    let mut current = start;
+    loop {
+        let slot = convert_to_type_ref::<cte_t>(current);
+        if !visit(slot) {
+            return false;
+        }
+        let next = step(slot);
+        if next == 0 {
+            return false;
+        }
+        if next == tortoise {
+            return true;
+        }
+        lam += 1;
+        if lam == power {
+            tortoise = next;
+            power *= 2;
+            lam = 0;
+        }
+        current = next;
+    }
+}
@@ -0,0 +1,117 @@
+//! 基于序列计数器的无锁`resolve_address_bits`
+//!
+//! 每个`slot`按地址映射到`SEQLOCK_STRIPES`个计数器之一。计数器的低8位是正在修改的写者数，
+//! 其余位是版本号：写者开始时加1，结束时减1并递增版本号，所以多个写者同时修改映射到同一计数器的
+//! 不同`slot`也不会让读者误以为没有写入。
+//!
+//! 读者在没有写者时读取计数器，复制`cap`，再确认计数器没有变化。
+//! `resolve_address_bits_lockfree`记录路径上每个`slot`的计数器，遍历结束后统一检查，
+//! 任何一级被修改过都从根重新查找，因此返回的路径和`cap`是某一时刻的一致快照。
+//! 读者从不加锁，`cte_move`、`cte_swap`、`set_empty`等写者也不会等待读者。
+use crate::cte::{cte_t, resolve_address_bits_walk};
+use crate::structures::resolveAddressBits_ret_t;
+use core::hint::spin_loop;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+use sel4_common::sel4_config::{SEL4_SLOT_BITS, WORD_BITS};
+use sel4_common::structures_gen::{cap, cap_null_cap};
+
+/// 计数器的个数，必须是2的幂
+pub const SEQLOCK_STRIPES: usize = 256;
+
+const WRITERS_MASK: usize = 0xff;
+const VERSION: usize = 1 << 8;
+
+static SEQ: [AtomicUsize; SEQLOCK_STRIPES] = [const { AtomicUsize::new(0) }; SEQLOCK_STRIPES];
+
+#[inline]
+fn counter(slot: usize) -> &'static AtomicUsize {
+    &SEQ[(slot >> SEL4_SLOT_BITS) & (SEQLOCK_STRIPES - 1)]
+}
+
+/// 正在修改若干`slot`中的`cap`，drop时结束写入
+pub(crate) struct SlotWrite<const N: usize>([usize; N]);
+
+impl<const N: usize> SlotWrite<N> {
+    #[inline]
+    pub(crate) fn begin(slots: [usize; N]) -> Self {
+        for slot in slots {
+            counter(slot).fetch_add(1, Ordering::Relaxed);
+        }
+        fence(Ordering::Release);
+        SlotWrite(slots)
+    }
+}
+
+impl<const N: usize> Drop for SlotWrite<N> {
+    #[inline]
+    fn drop(&mut self) {
+        for slot in self.0 {
+            counter(slot).fetch_add(VERSION - 1, Ordering::Release);
+        }
+    }
+}
+
+/// 读取`slot`中`cap`的一致快照，同时返回读取时的计数器
+fn read_slot(slot: *const cte_t) -> (cap, usize) {
+    let seq_counter = counter(slot as usize);
+    loop {
+        let seq = seq_counter.load(Ordering::Acquire);
+        if seq & WRITERS_MASK != 0 {
+            spin_loop();
+            continue;
+        }
+        let capability = unsafe { ptr::read_volatile(&(*slot).capability) };
+        fence(Ordering::Acquire);
+        if seq_counter.load(Ordering::Relaxed) == seq {
+            return (capability, seq);
+        }
+    }
+}
+
+/// `resolve_address_bits_lockfree`的结果
+pub struct LookupSnapshot {
+    pub ret: resolveAddressBits_ret_t,
+    /// 查找结束时`ret.slot`中的`cap`，查找失败时为`null_cap`
+    pub capability: cap,
+}
+
+/// 同`resolve_address_bits`，但不需要持有写者的锁，遇到并发修改时重试
+///
+/// 调用者应当使用返回的`capability`，而不是再次读取`ret.slot`，后者在返回之后随时可能被修改。
+pub fn resolve_address_bits_lockfree(
+    node_cap: &cap,
+    cap_ptr: usize,
+    n_bits: usize,
+) -> LookupSnapshot {
+    loop {
+        // 每一级至少消耗1位，再加上最后的`slot`。
+        // `n_bits`超过字长时路径可能更长，记录满之后当作遇到了非`CNode`的`cap`结束查找，并为最后的`slot`保留一项
+        let mut reads = [(0usize, 0usize); WORD_BITS + 1];
+        let mut count = 0;
+        let ret = resolve_address_bits_walk(node_cap, cap_ptr, n_bits, |slot| {
+            if count == reads.len() - 1 {
+                return cap_null_cap::new().unsplay();
+            }
+            let (capability, seq) = read_slot(slot);
+            reads[count] = (slot as usize, seq);
+            count += 1;
+            capability
+        });
+        let capability = if ret.slot.is_null() {
+            cap_null_cap::new().unsplay()
+        } else {
+            let (capability, seq) = read_slot(ret.slot);
+            reads[count] = (ret.slot as usize, seq);
+            count += 1;
+            capability
+        };
+        fence(Ordering::Acquire);
+        if reads[..count]
+            .iter()
+            .all(|&(slot, seq)| counter(slot).load(Ordering::Relaxed) == seq)
+        {
+            return LookupSnapshot { ret, capability };
+        }
+    }
+}
@@ -31,6 +31,68 @@ impl Default for finaliseSlot_ret {
     }
 }
 
+/// `delete_all_with`的结果
+///
+/// Arguments:
+///
+/// slots_deleted: 本次调用中被清空的`slot`数量，包括`CNode`和`TCB`中被连带清除的`slot`
+///
+/// in_progress: 没有完成时仍保存着`cap`（通常是`zombie_cap`）的`slot`地址，完成时为0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeleteProgress {
+    pub status: exception_t,
+    pub slots_deleted: usize,
+    pub in_progress: usize,
+}
+
+impl Default for DeleteProgress {
+    fn default() -> Self {
+        DeleteProgress {
+            status: exception_t::EXCEPTION_NONE,
+            slots_deleted: 0,
+            in_progress: 0,
+        }
+    }
+}
+
+impl DeleteProgress {
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.status == exception_t::EXCEPTION_NONE && self.in_progress == 0
+    }
+}
+
+/// `revoke_with`的结果
+///
+/// Arguments:
+///
+/// slots_deleted: 本次调用中被清空的`slot`数量
+///
+/// in_progress: 删除被打断的子节点地址，在两个子节点之间被抢占时为下一个待删除的子节点，完成时为0
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RevokeProgress {
+    pub status: exception_t,
+    pub slots_deleted: usize,
+    pub in_progress: usize,
+}
+
+impl Default for RevokeProgress {
+    fn default() -> Self {
+        RevokeProgress {
+            status: exception_t::EXCEPTION_NONE,
+            slots_deleted: 0,
+            in_progress: 0,
+        }
+    }
+}
+
+impl RevokeProgress {
+    #[inline]
+    pub fn is_complete(&self) -> bool {
+        self.status == exception_t::EXCEPTION_NONE && self.in_progress == 0
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, PartialEq, Clone)]
 pub struct FinaliseCapRet {
@@ -38,12 +100,41 @@ pub struct FinaliseCapRet {
     pub cleanupInfo: cap,
 }
 
+/// 寻址失败的具体原因，与seL4中`lookup_fault`的几种类型一一对应
+///
+/// Arguments:
+///
+/// bits_left: 出错时还未解析的`cap_ptr`位数
+///
+/// guard_found: 从`cap_ptr`中取出的guard，guard_expected: `CNode`中记录的guard
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LookupFault {
+    #[default]
+    None,
+    /// `seL4_InvalidRoot`：根节点不是`CNode`
+    InvalidRoot,
+    /// `seL4_MissingCapability`：找到的`slot`中没有所需的`cap`
+    MissingCapability { bits_left: usize },
+    /// `seL4_DepthMismatch`：剩余位数不足以解析当前层级
+    DepthMismatch { bits_found: usize, bits_left: usize },
+    /// `seL4_GuardMismatch`：guard不匹配
+    GuardMismatch {
+        guard_found: usize,
+        guard_expected: usize,
+        bits_left: usize,
+        guard_size: usize,
+    },
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct resolveAddressBits_ret_t {
     pub status: exception_t,
     pub slot: *mut cte_t,
     pub bitsRemaining: usize,
+    /// `status`为`EXCEPTION_LOOKUP_FAULT`时记录失败原因
+    pub lookupFault: LookupFault,
 }
 
 impl Default for resolveAddressBits_ret_t {
@@ -53,6 +144,7 @@ impl Default for resolveAddressBits_ret_t {
             status: exception_t::EXCEPTION_NONE,
             slot: core::ptr::null_mut::<cte_t>(),
             bitsRemaining: 0,
+            lookupFault: LookupFault::None,
         }
     }
 }
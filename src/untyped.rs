@@ -0,0 +1,217 @@
+//! 从`untyped`中创建内核对象，对应seL4中的`decodeUntypedInvocation`和`invokeUntyped_Retype`
+//!
+//! 这里负责参数检查、对齐、剩余空间计算、`untyped`的重置、`capFreeIndex`的维护和`cap`的插入，
+//! 对象本身的初始化和内存清零由`UntypedHooks`完成。
+use crate::cte::{cte_t, insert_new_cap, slots_changing};
+use crate::deps::{CSpaceHooks, UntypedHooks};
+use crate::invocation::{lookup_slot, CNodeError, SlotAddr};
+use crate::structures::LookupFault;
+use sel4_common::object::ObjectType;
+use sel4_common::sel4_config::*;
+use sel4_common::structures::exception_t;
+use sel4_common::structures_gen::{cap, cap_tag};
+use sel4_common::utils::convert_to_type_ref;
+
+/// `seL4_ObjectType`中与架构无关的部分，编号取自`sel4_common`的`ObjectType`，其余编号由内核解释
+pub const SEL4_UNTYPED_OBJECT: usize = ObjectType::UnytpedObject as usize;
+pub const SEL4_TCB_OBJECT: usize = ObjectType::TCBObject as usize;
+pub const SEL4_ENDPOINT_OBJECT: usize = ObjectType::EndpointObject as usize;
+pub const SEL4_NOTIFICATION_OBJECT: usize = ObjectType::NotificationObject as usize;
+pub const SEL4_CAP_TABLE_OBJECT: usize = ObjectType::CapTableObject as usize;
+#[cfg(feature = "kernel_mcs")]
+pub const SEL4_SCHED_CONTEXT_OBJECT: usize = ObjectType::SchedContextObject as usize;
+#[cfg(feature = "kernel_mcs")]
+pub const SEL4_REPLY_OBJECT: usize = ObjectType::ReplyObject as usize;
+
+/// 重置`untyped`时每次清零的大小，与seL4默认的`CONFIG_RESET_CHUNK_BITS`一致
+pub const RESET_CHUNK_BITS: usize = 8;
+
+impl cte_t {
+    /// 重置没有子节点的`untyped`，将已分配的部分清零并把`capFreeIndex`恢复为0，内核回调由`H`提供
+    ///
+    /// 从高地址向低地址每次清零`2^RESET_CHUNK_BITS`字节，每块之后更新`capFreeIndex`并调用`preemption_point`，
+    /// 被抢占后再次调用会从上次停下的位置继续。设备内存和小于一块的`untyped`不分块，设备内存不清零。
+    pub fn reset_untyped_with<H: CSpaceHooks + UntypedHooks>(&mut self) -> exception_t {
+        assert_eq!(self.capability.get_tag(), cap_tag::cap_untyped_cap);
+        if self.ensure_no_children() != exception_t::EXCEPTION_NONE {
+            return exception_t::EXCEPTION_SYSCALL_ERROR;
+        }
+        let self_ptr = self.get_ptr();
+        let untyped = cap::cap_untyped_cap(&self.capability);
+        let block_size = untyped.get_capBlockSize() as usize;
+        let region_base = untyped.get_capPtr() as usize;
+        let mut offset = (untyped.get_capFreeIndex() as usize) << SEL4_MIN_UNTYPED_BITS;
+        if offset == 0 {
+            return exception_t::EXCEPTION_NONE;
+        }
+        let device = untyped.get_capIsDevice() != 0;
+        if device || block_size < RESET_CHUNK_BITS {
+            if !device {
+                H::clear_memory(region_base, block_size);
+            }
+            let _write = slots_changing([self_ptr]);
+            untyped.set_capFreeIndex(0);
+            return exception_t::EXCEPTION_NONE;
+        }
+        offset = (offset - 1) & !mask_bits!(RESET_CHUNK_BITS);
+        loop {
+            H::clear_memory(region_base + offset, RESET_CHUNK_BITS);
+            {
+                let _write = slots_changing([self_ptr]);
+                untyped.set_capFreeIndex((offset >> SEL4_MIN_UNTYPED_BITS) as u64);
+            }
+            let status = H::preemption_point();
+            if status != exception_t::EXCEPTION_NONE {
+                return status;
+            }
+            if offset == 0 {
+                return exception_t::EXCEPTION_NONE;
+            }
+            offset -= 1 << RESET_CHUNK_BITS;
+        }
+    }
+}
+
+/// 对象的大小（以2为底的对数），TCB和架构相关对象由`H`给出，`obj_type`无效时返回`None`
+///
+/// 过大的`user_size`不会导致溢出，由调用者检查结果是否超过`SEL4_MAX_UNTYPED_BITS`
+pub fn object_size<H: UntypedHooks>(obj_type: usize, user_size: usize) -> Option<usize> {
+    match obj_type {
+        SEL4_UNTYPED_OBJECT => Some(user_size),
+        SEL4_ENDPOINT_OBJECT => Some(SEL4_ENDPOINT_BITS),
+        SEL4_NOTIFICATION_OBJECT => Some(SEL4_NOTIFICATION_BITS),
+        SEL4_CAP_TABLE_OBJECT => Some(user_size.saturating_add(SEL4_SLOT_BITS)),
+        #[cfg(feature = "kernel_mcs")]
+        SEL4_SCHED_CONTEXT_OBJECT => Some(user_size),
+        #[cfg(feature = "kernel_mcs")]
+        SEL4_REPLY_OBJECT => Some(SEL4_REPLY_BITS),
+        _ => H::object_size(obj_type, user_size),
+    }
+}
+
+/// 从`free_index`开始，下一个按`object_size`对齐的地址
+pub fn aligned_free_ref(untyped: &cap, free_index: usize, object_size: usize) -> usize {
+    let free_ref =
+        cap::cap_untyped_cap(untyped).get_capPtr() as usize + (free_index << SEL4_MIN_UNTYPED_BITS);
+    (free_ref + mask_bits!(object_size)) & !mask_bits!(object_size)
+}
+
+/// 从`free_index`开始还能创建多少个大小为`object_size`的对象
+pub fn available_objects(untyped: &cap, free_index: usize, object_size: usize) -> usize {
+    let block_size = cap::cap_untyped_cap(untyped).get_capBlockSize() as usize;
+    let free_bytes = (1usize << block_size) - (free_index << SEL4_MIN_UNTYPED_BITS);
+    free_bytes >> object_size
+}
+
+/// `seL4_Untyped_Retype`，对象的大小、初始化和内存清零由`H`提供
+///
+/// 在`dest`指定的`CNode`中从`node_offset`开始的`node_window`个空`slot`里创建对象，
+/// `dest.depth`为0时直接使用`dest.root`作为目标`CNode`。
+/// `untyped`没有子节点时先通过`reset_untyped_with`重置，被抢占时返回`CNodeError::Interrupted`，
+/// 重新调用会继续重置。
+pub fn untyped_retype_with<H: CSpaceHooks + UntypedHooks>(
+    untyped_slot: &mut cte_t,
+    obj_type: usize,
+    user_size: usize,
+    dest: SlotAddr,
+    node_offset: usize,
+    node_window: usize,
+) -> Result<(), CNodeError> {
+    if untyped_slot.capability.get_tag() != cap_tag::cap_untyped_cap {
+        return Err(CNodeError::IllegalOperation);
+    }
+    // 与seL4的`decodeUntypedInvocation`一致，先检查对象类型，再检查用户给出的大小和对象大小
+    let size =
+        object_size::<H>(obj_type, user_size).ok_or(CNodeError::InvalidArgument { index: 0 })?;
+    if user_size >= WORD_BITS || size > SEL4_MAX_UNTYPED_BITS {
+        return Err(CNodeError::RangeError {
+            min: 0,
+            max: SEL4_MAX_UNTYPED_BITS,
+        });
+    }
+    if (obj_type == SEL4_CAP_TABLE_OBJECT && user_size == 0)
+        || (obj_type == SEL4_UNTYPED_OBJECT && user_size < SEL4_MIN_UNTYPED_BITS)
+    {
+        return Err(CNodeError::InvalidArgument { index: 1 });
+    }
+
+    let dest_cnode = if dest.depth == 0 {
+        dest.root.clone()
+    } else {
+        lookup_slot(&dest, false)?.capability.clone()
+    };
+    if dest_cnode.get_tag() != cap_tag::cap_cnode_cap {
+        return Err(CNodeError::FailedLookup {
+            source: false,
+            fault: LookupFault::MissingCapability {
+                bits_left: dest.depth,
+            },
+        });
+    }
+    let cnode = cap::cap_cnode_cap(&dest_cnode);
+    let node_size = 1usize << cnode.get_capCNodeRadix();
+    if node_offset > node_size - 1 {
+        return Err(CNodeError::RangeError {
+            min: 0,
+            max: node_size - 1,
+        });
+    }
+    if node_window < 1 || node_window > CONFIG_RETYPE_FAN_OUT_LIMIT {
+        return Err(CNodeError::RangeError {
+            min: 1,
+            max: CONFIG_RETYPE_FAN_OUT_LIMIT,
+        });
+    }
+    if node_window > node_size - node_offset {
+        return Err(CNodeError::RangeError {
+            min: 1,
+            max: node_size - node_offset,
+        });
+    }
+    let first_slot = convert_to_type_ref::<cte_t>(cnode.get_capCNodePtr() as usize);
+    for i in node_offset..node_offset + node_window {
+        if first_slot.get_offset_slot(i).capability.get_tag() != cap_tag::cap_null_cap {
+            return Err(CNodeError::DeleteFirst);
+        }
+    }
+
+    let untyped = untyped_slot.capability.clone();
+    let reset = untyped_slot.ensure_no_children() == exception_t::EXCEPTION_NONE;
+    let free_index = if reset {
+        0
+    } else {
+        cap::cap_untyped_cap(&untyped).get_capFreeIndex() as usize
+    };
+    let available = available_objects(&untyped, free_index, size);
+    if available < node_window {
+        return Err(CNodeError::NotEnoughMemory { available });
+    }
+    let device = cap::cap_untyped_cap(&untyped).get_capIsDevice() != 0;
+    if device && obj_type != SEL4_UNTYPED_OBJECT && !H::is_frame_type(obj_type) {
+        return Err(CNodeError::InvalidArgument { index: 1 });
+    }
+
+    if reset {
+        let status = untyped_slot.reset_untyped_with::<H>();
+        if status != exception_t::EXCEPTION_NONE {
+            return Err(CNodeError::Interrupted(status));
+        }
+    }
+    let region_base = aligned_free_ref(&untyped, free_index, size);
+    let free_ref = region_base + (node_window << size);
+    let base = cap::cap_untyped_cap(&untyped).get_capPtr() as usize;
+    {
+        let _write = slots_changing([untyped_slot.get_ptr()]);
+        cap::cap_untyped_cap(&untyped_slot.capability)
+            .set_capFreeIndex(((free_ref - base) >> SEL4_MIN_UNTYPED_BITS) as u64);
+    }
+    for i in 0..node_window {
+        let new_cap = H::create_object(obj_type, region_base + (i << size), user_size, device);
+        insert_new_cap(
+            untyped_slot,
+            first_slot.get_offset_slot(node_offset + i),
+            &new_cap,
+        );
+    }
+    Ok(())
+}
@@ -2,9 +2,16 @@
 
 echo "ARGS1 $1"
 
-rust-objcopy --binary-architecture=riscv64 $1 --strip-all -O binary $1.bin
+# 根据 ELF 的位宽选择 riscv64 或 riscv32 的 QEMU
+if [ "$(od -An -tx1 -j4 -N1 $1 | tr -d ' ')" = "01" ]; then
+    ARCH=riscv32
+else
+    ARCH=riscv64
+fi
 
-qemu-system-riscv64 \
+rust-objcopy --binary-architecture=$ARCH $1 --strip-all -O binary $1.bin
+
+qemu-system-$ARCH \
     -machine virt \
     -kernel $1.bin \
     -nographic -smp 1 \